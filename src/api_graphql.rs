@@ -0,0 +1,411 @@
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+use async_graphql::{dataloader::{DataLoader, Loader}, http::GraphiQLSource, Context, EmptyMutation, EmptySubscription, InputObject, Object, Schema, SimpleObject};
+use axum::{extract::State, response::Html, routing::get, Json, Router};
+use chrono::Utc;
+use futures::TryStreamExt;
+use mongodb::bson::{doc, DateTime, Document};
+use tracing::error;
+use crate::{api_rest::{ApiState, PageFilter}, error::WikidotError, mongo_page::MongoPage, mongo_user::MongoUser, mongo_votes::MongoUserVote};
+
+pub type ApiSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+const MAX_DEPTH: usize = 12;
+// Lists are costed at their expected length times their items, so a page list with every voter is refused
+const MAX_COMPLEXITY: usize = 10_000;
+const VOTES_COST: usize = 100;
+const REVISIONS_COST: usize = 50;
+const AUTHORED_PAGES_COST: usize = 20;
+const KARMA_COST: usize = 10;
+const AUTHORS_COST: usize = 3;
+
+// Sources are never exposed and make up most of a page document, older documents still embed revision sources
+fn page_projection() -> Document{
+    doc! {"source": 0, "history.source": 0}
+}
+
+fn time(date: DateTime) -> chrono::DateTime<Utc>{
+    chrono::DateTime::from_timestamp_millis(date.timestamp_millis()).unwrap_or_default()
+}
+
+async fn find_pages(collection: &mongodb::Collection<MongoPage>, filter: Document) -> Result<Vec<Arc<MongoPage>>, WikidotError>{
+    Ok(collection.find(filter)
+        .projection(page_projection())
+        .await?
+        .map_ok(Arc::new)
+        .try_collect().await?)
+}
+
+pub struct UserLoader(mongodb::Collection<MongoUser>);
+
+impl Loader<i32> for UserLoader{
+    type Value = Arc<MongoUser>;
+    type Error = Arc<WikidotError>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error>{
+        let cursor = self.0.find(doc! {"id": {"$in": keys}}).await.map_err(|e| Arc::new(e.into()))?;
+        let users = cursor.try_collect::<Vec<_>>().await.map_err(|e| Arc::new(e.into()))?;
+        Ok(users.into_iter().map(|user| (user.id, Arc::new(user))).collect())
+    }
+}
+
+pub struct PageLoader(mongodb::Collection<MongoPage>);
+
+impl Loader<i32> for PageLoader{
+    type Value = Arc<MongoPage>;
+    type Error = Arc<WikidotError>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error>{
+        let pages = find_pages(&self.0, doc! {"id": {"$in": keys}}).await.map_err(Arc::new)?;
+        Ok(pages.into_iter().map(|page| (page.id, page)).collect())
+    }
+}
+
+// Pages keyed by each of their authors
+pub struct AuthoredPagesLoader(mongodb::Collection<MongoPage>);
+
+impl Loader<i32> for AuthoredPagesLoader{
+    type Value = Vec<Arc<MongoPage>>;
+    type Error = Arc<WikidotError>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error>{
+        let pages = find_pages(&self.0, doc! {"author": {"$in": keys}}).await.map_err(Arc::new)?;
+        let mut authored: HashMap<i32, Self::Value> = HashMap::new();
+        for page in pages{
+            for author in page.author.iter().filter(|author| keys.contains(author)){
+                authored.entry(*author).or_default().push(page.clone());
+            }
+        }
+        Ok(authored)
+    }
+}
+
+// Current votes of each user, as of the last user vote rebuild
+pub struct VotesCastLoader(mongodb::Collection<MongoUserVote>, mongodb::Collection<MongoPage>);
+
+impl Loader<i32> for VotesCastLoader{
+    type Value = Vec<(Arc<MongoPage>, i8)>;
+    type Error = Arc<WikidotError>;
+
+    async fn load(&self, keys: &[i32]) -> Result<HashMap<i32, Self::Value>, Self::Error>{
+        let votes = self.0.find(doc! {"user_id": {"$in": keys}, "value": {"$ne": 0}})
+            .sort(doc! {"user_id": 1, "first_seen": 1})
+            .await.map_err(|e| Arc::new(e.into()))?
+            .try_collect::<Vec<_>>().await.map_err(|e| Arc::new(e.into()))?;
+        let page_ids = votes.iter().map(|vote| vote.page_id).collect::<Vec<_>>();
+        let pages = find_pages(&self.1, doc! {"id": {"$in": page_ids}}).await.map_err(Arc::new)?
+            .into_iter()
+            .map(|page| (page.id, page))
+            .collect::<HashMap<_, _>>();
+        let mut cast: HashMap<i32, Self::Value> = HashMap::new();
+        for vote in votes{
+            if let Some(page) = pages.get(&vote.page_id) {
+                cast.entry(vote.user_id).or_default().push((page.clone(), vote.value));
+            }
+        }
+        Ok(cast)
+    }
+}
+
+// Same fields as the REST query string
+#[derive(InputObject, Default)]
+#[graphql(name = "PageFilter")]
+pub struct PageFilterInput{
+    /// Comma separated, every tag must match
+    tags: Option<String>,
+    author: Option<i32>,
+    status: Option<bool>,
+    min_rating: Option<i32>,
+    max_rating: Option<i32>,
+    page: Option<u64>,
+    per_page: Option<u64>,
+}
+
+// Pages a `pages` query can return, which is what its items cost
+fn page_count(filter: &Option<PageFilterInput>) -> usize{
+    let pagination = PageFilter{per_page: filter.as_ref().and_then(|filter| filter.per_page), ..PageFilter::default()}.pagination();
+    pagination.limit() as usize
+}
+
+impl From<PageFilterInput> for PageFilter{
+    fn from(input: PageFilterInput) -> Self{
+        PageFilter{
+            tags: input.tags,
+            author: input.author,
+            status: input.status,
+            min_rating: input.min_rating,
+            max_rating: input.max_rating,
+            page: input.page,
+            per_page: input.per_page,
+        }
+    }
+}
+
+// Same as the REST API, database details stay in the log
+fn internal(e: impl Display) -> async_graphql::Error{
+    error!(error = %e, "graphql request failed");
+    async_graphql::Error::new("internal error")
+}
+
+async fn load_user(ctx: &Context<'_>, id: i32) -> async_graphql::Result<Option<UserNode>>{
+    Ok(ctx.data_unchecked::<DataLoader<UserLoader>>().load_one(id).await.map_err(internal)?.map(UserNode))
+}
+
+pub struct PageNode(Arc<MongoPage>);
+
+#[Object(name = "Page")]
+impl PageNode{
+    async fn id(&self) -> i32{
+        self.0.id
+    }
+
+    async fn fullname(&self) -> &str{
+        &self.0.fullname
+    }
+
+    async fn title(&self) -> &str{
+        &self.0.title
+    }
+
+    async fn alternative(&self) -> &str{
+        &self.0.alternative
+    }
+
+    async fn tags(&self) -> &[String]{
+        &self.0.tags
+    }
+
+    /// False once the page is gone from the site
+    async fn status(&self) -> bool{
+        self.0.status
+    }
+
+    async fn comments_count(&self) -> i16{
+        self.0.comments_count
+    }
+
+    async fn up(&self) -> i16{
+        self.0.rate_history.last().map_or(0, |rate| rate.up)
+    }
+
+    async fn down(&self) -> i16{
+        self.0.rate_history.last().map_or(0, |rate| rate.down)
+    }
+
+    async fn rating(&self) -> i16{
+        self.0.rate_history.last().map_or(0, |rate| rate.up - rate.down)
+    }
+
+    #[graphql(complexity = "AUTHORS_COST * child_complexity")]
+    async fn authors(&self) -> Vec<AuthorNode>{
+        self.0.author.iter().map(|id| AuthorNode(*id)).collect()
+    }
+
+    #[graphql(complexity = "REVISIONS_COST * child_complexity")]
+    async fn revisions(&self) -> Vec<RevisionNode>{
+        (0..self.0.history.len()).map(|i| RevisionNode(self.0.clone(), i)).collect()
+    }
+
+    /// Votes as last recorded at `at`, or the current ones
+    #[graphql(complexity = "VOTES_COST * child_complexity")]
+    async fn votes(&self, at: Option<chrono::DateTime<Utc>>) -> Vec<VoteNode>{
+        let at = at.map_or(DateTime::MAX, |at| DateTime::from_millis(at.timestamp_millis()));
+        self.0.votes_at(at).into_iter()
+            .map(|(user_id, value)| VoteNode{user_id, value})
+            .collect()
+    }
+}
+
+pub struct AuthorNode(i32);
+
+#[Object(name = "Author")]
+impl AuthorNode{
+    async fn id(&self) -> i32{
+        self.0
+    }
+
+    /// Missing when the author was never archived
+    async fn user(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<UserNode>>{
+        load_user(ctx, self.0).await
+    }
+}
+
+pub struct RevisionNode(Arc<MongoPage>, usize);
+
+#[Object(name = "Revision")]
+impl RevisionNode{
+    async fn index(&self) -> i16{
+        self.0.history[self.1].index
+    }
+
+    async fn id(&self) -> i32{
+        self.0.history[self.1].id
+    }
+
+    async fn types(&self) -> Vec<String>{
+        self.0.history[self.1].types.iter().map(char::to_string).collect()
+    }
+
+    async fn created_at(&self) -> Option<chrono::DateTime<Utc>>{
+        self.0.history[self.1].created_at.map(time)
+    }
+
+    async fn comment(&self) -> &str{
+        &self.0.history[self.1].comment
+    }
+
+    async fn created_by(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<UserNode>>{
+        let Some(user_id) = self.0.history[self.1].created_by else { return Ok(None) };
+        load_user(ctx, user_id).await
+    }
+}
+
+pub struct VoteNode{
+    user_id: i32,
+    value: i8,
+}
+
+#[Object(name = "Vote")]
+impl VoteNode{
+    async fn user_id(&self) -> i32{
+        self.user_id
+    }
+
+    async fn value(&self) -> i8{
+        self.value
+    }
+
+    async fn user(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<UserNode>>{
+        load_user(ctx, self.user_id).await
+    }
+}
+
+pub struct VoteCastNode(Arc<MongoPage>, i8);
+
+#[Object(name = "VoteCast")]
+impl VoteCastNode{
+    async fn page(&self) -> PageNode{
+        PageNode(self.0.clone())
+    }
+
+    async fn value(&self) -> i8{
+        self.1
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Karma")]
+pub struct KarmaEntry{
+    level: i8,
+    timestamp: chrono::DateTime<Utc>,
+}
+
+pub struct UserNode(Arc<MongoUser>);
+
+#[Object(name = "User")]
+impl UserNode{
+    async fn id(&self) -> i32{
+        self.0.id
+    }
+
+    async fn join(&self) -> chrono::DateTime<Utc>{
+        time(self.0.join)
+    }
+
+    async fn account_type(&self) -> &str{
+        &self.0.account_type
+    }
+
+    async fn title(&self) -> Option<&str>{
+        self.0.title.last().map(String::as_str)
+    }
+
+    async fn avatar(&self) -> Option<&str>{
+        self.0.avatar.last().map(|avatar| avatar.image.as_str())
+    }
+
+    #[graphql(complexity = "KARMA_COST * child_complexity")]
+    async fn karma_history(&self) -> Vec<KarmaEntry>{
+        self.0.karma.iter().map(|karma| KarmaEntry{level: karma.level, timestamp: time(karma.timestamp)}).collect()
+    }
+
+    #[graphql(complexity = "AUTHORED_PAGES_COST * child_complexity")]
+    async fn pages(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<PageNode>>{
+        let pages = ctx.data_unchecked::<DataLoader<AuthoredPagesLoader>>().load_one(self.0.id).await.map_err(internal)?;
+        Ok(pages.unwrap_or_default().into_iter().map(PageNode).collect())
+    }
+
+    #[graphql(complexity = "VOTES_COST * child_complexity")]
+    async fn votes_cast(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<VoteCastNode>>{
+        let votes = ctx.data_unchecked::<DataLoader<VotesCastLoader>>().load_one(self.0.id).await.map_err(internal)?;
+        Ok(votes.unwrap_or_default().into_iter().map(|(page, value)| VoteCastNode(page, value)).collect())
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object(name = "Query")]
+impl QueryRoot{
+    /// Looks a page up by id, or by fullname preferring the live copy
+    async fn page(&self, ctx: &Context<'_>, id: Option<i32>, fullname: Option<String>) -> async_graphql::Result<Option<PageNode>>{
+        if let Some(id) = id {
+            return Ok(ctx.data_unchecked::<DataLoader<PageLoader>>().load_one(id).await.map_err(internal)?.map(PageNode))
+        }
+        let Some(fullname) = fullname else { return Err("either id or fullname is required".into()) };
+        let page = ctx.data_unchecked::<ApiState>().pages
+            .find_one(doc! {"fullname": fullname})
+            .projection(page_projection())
+            .sort(doc! {"status": -1})
+            .await
+            .map_err(internal)?;
+        Ok(page.map(|page| PageNode(Arc::new(page))))
+    }
+
+    #[graphql(complexity = "page_count(&filter) * child_complexity")]
+    async fn pages(&self, ctx: &Context<'_>, filter: Option<PageFilterInput>) -> async_graphql::Result<Vec<PageNode>>{
+        let filter = PageFilter::from(filter.unwrap_or_default());
+        let pagination = filter.pagination();
+        let pages = ctx.data_unchecked::<ApiState>().pages
+            .find(filter.to_doc())
+            .projection(page_projection())
+            .sort(doc! {"id": 1})
+            .skip(pagination.skip())
+            .limit(pagination.limit() as i64)
+            .await
+            .map_err(internal)?
+            .map_ok(|page| PageNode(Arc::new(page)))
+            .try_collect()
+            .await
+            .map_err(internal)?;
+        Ok(pages)
+    }
+
+    async fn user(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<Option<UserNode>>{
+        load_user(ctx, id).await
+    }
+}
+
+pub fn schema(state: &ApiState) -> ApiSchema{
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(DataLoader::new(UserLoader(state.users.clone()), tokio::spawn))
+        .data(DataLoader::new(PageLoader(state.pages.clone()), tokio::spawn))
+        .data(DataLoader::new(AuthoredPagesLoader(state.pages.clone()), tokio::spawn))
+        .data(DataLoader::new(VotesCastLoader(state.user_votes.clone(), state.pages.clone()), tokio::spawn))
+        .data(state.clone())
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+async fn graphql(State(schema): State<ApiSchema>, Json(request): Json<async_graphql::Request>) -> Json<async_graphql::Response>{
+    Json(schema.execute(request).await)
+}
+
+async fn graphiql() -> Html<String>{
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+pub fn router(state: &ApiState) -> Router{
+    Router::new()
+        .route("/graphql", get(graphiql).post(graphql))
+        .with_state(schema(state))
+}
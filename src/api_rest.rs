@@ -0,0 +1,312 @@
+use axum::{extract::{Path, Query, State}, http::{header, HeaderMap, StatusCode}, response::{Html, IntoResponse, Response}, routing::get, Json, Router};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::error;
+use crate::{error::{TargetNotExist, WikidotError}, mongo_page::MongoPage, mongo_revision::MongoRevisionSource, mongo_user::MongoUser, mongo_votes::{vote_summary, MongoUserVote}, page_html::render_archived_page};
+
+const PER_PAGE: u64 = 50;
+const MAX_PER_PAGE: u64 = 200;
+
+#[derive(Clone)]
+pub struct ApiState{
+    pub pages: mongodb::Collection<MongoPage>,
+    pub users: mongodb::Collection<MongoUser>,
+    pub user_votes: mongodb::Collection<MongoUserVote>,
+    pub revisions: mongodb::Collection<MongoRevisionSource>,
+    // Rendered pages link their images and files here, see `HtmlOptions::file_base`
+    pub file_base: String,
+}
+
+impl ApiState{
+    pub fn new(db: &mongodb::Database, site: &str) -> Self{
+        ApiState{
+            pages: db.collection("pages"),
+            users: db.collection("users"),
+            user_votes: db.collection("user_votes"),
+            revisions: db.collection("revisions"),
+            file_base: format!("https://{site}.wikidot.com/local--files/"),
+        }
+    }
+
+    fn page_docs(&self) -> mongodb::Collection<Document>{
+        self.pages.clone_with_type()
+    }
+
+    fn user_docs(&self) -> mongodb::Collection<Document>{
+        self.users.clone_with_type()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Pagination{
+    page: Option<u64>,
+    per_page: Option<u64>,
+}
+
+impl Pagination{
+    pub(crate) fn page(&self) -> u64{
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub(crate) fn limit(&self) -> u64{
+        self.per_page.unwrap_or(PER_PAGE).clamp(1, MAX_PER_PAGE)
+    }
+
+    pub(crate) fn skip(&self) -> u64{
+        (self.page() - 1) * self.limit()
+    }
+}
+
+// `tags` is comma separated and every tag must match, the rating range is inclusive
+#[derive(Deserialize, Default)]
+pub struct PageFilter{
+    pub tags: Option<String>,
+    pub author: Option<i32>,
+    pub status: Option<bool>,
+    pub min_rating: Option<i32>,
+    pub max_rating: Option<i32>,
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+}
+
+impl PageFilter{
+    pub(crate) fn pagination(&self) -> Pagination{
+        Pagination{page: self.page, per_page: self.per_page}
+    }
+
+    pub(crate) fn to_doc(&self) -> Document{
+        let mut filter = doc! {};
+        if let Some(tags) = &self.tags {
+            let tags = tags.split(',').map(str::trim).filter(|tag| !tag.is_empty()).collect::<Vec<_>>();
+            if !tags.is_empty() {
+                filter.insert("tags", doc! {"$all": tags});
+            }
+        }
+        if let Some(author) = self.author {
+            filter.insert("author", author);
+        }
+        if let Some(status) = self.status {
+            filter.insert("status", status);
+        }
+        let rating = doc! {"$subtract": [
+            {"$arrayElemAt": ["$rate_history.up", -1]},
+            {"$arrayElemAt": ["$rate_history.down", -1]},
+        ]};
+        let mut range = Vec::new();
+        if let Some(min) = self.min_rating {
+            range.push(doc! {"$gte": [rating.clone(), min]});
+        }
+        if let Some(max) = self.max_rating {
+            range.push(doc! {"$lte": [rating, max]});
+        }
+        if !range.is_empty() {
+            filter.insert("$expr", doc! {"$and": range});
+        }
+        filter
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RevisionQuery{
+    revision: Option<i16>,
+}
+
+#[derive(Serialize)]
+struct Paged{
+    items: Vec<Value>,
+    page: u64,
+    per_page: u64,
+    total: u64,
+}
+
+impl IntoResponse for WikidotError{
+    fn into_response(self) -> Response{
+        match self {
+            WikidotError::TargetNotExist(e) => (StatusCode::NOT_FOUND, Json(json!({"error": e.to_string()}))).into_response(),
+            e => {
+                error!(error = %e, "api request failed");
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": "internal error"}))).into_response()
+            },
+        }
+    }
+}
+
+// Dates become RFC 3339 strings instead of extended JSON
+pub(crate) fn to_json(bson: Bson) -> Value{
+    match bson {
+        Bson::DateTime(date) => Value::String(date.try_to_rfc3339_string().unwrap_or_default()),
+        Bson::Document(doc) => Value::Object(doc.into_iter().map(|(key, value)| (key, to_json(value))).collect()),
+        Bson::Array(array) => Value::Array(array.into_iter().map(to_json).collect()),
+        bson => bson.into_relaxed_extjson(),
+    }
+}
+
+// Replaces the rate history slice with the latest rating, keeping the vote map for single page views
+fn flatten_rating(mut page: Document, votes: bool) -> Value{
+    if let Ok(mut history) = page.get_array_mut("rate_history").map(std::mem::take) {
+        if let Some(Bson::Document(last)) = history.pop() {
+            let up = last.get_i32("up").unwrap_or_default();
+            let down = last.get_i32("down").unwrap_or_default();
+            page.insert("up", up);
+            page.insert("down", down);
+            page.insert("rating", up - down);
+            if let Ok(timestamp) = last.get_datetime("timestamp") {
+                page.insert("rated_at", *timestamp);
+            }
+            if votes {
+                page.insert("votes", last.get("votes").cloned().unwrap_or(Bson::Document(doc! {})));
+            }
+        }
+    }
+    page.remove("rate_history");
+    to_json(Bson::Document(page))
+}
+
+// FNV-1a, stable across restarts unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64{
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// Answers 304 when the client already holds the same body
+fn with_etag(headers: &HeaderMap, value: &impl Serialize) -> Response{
+    let body = serde_json::to_vec(value).unwrap_or_default();
+    let etag = format!("\"{:016x}\"", fnv1a(&body));
+    let matched = headers.get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+    if matched {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response()
+    }
+    ([(header::ETAG, etag), (header::CONTENT_TYPE, String::from("application/json"))], body).into_response()
+}
+
+async fn find_summaries(state: &ApiState, filter: Document, pagination: &Pagination) -> Result<Paged, WikidotError>{
+    let total = state.pages.count_documents(filter.clone()).await?;
+    let items = state.page_docs()
+        .find(filter)
+        .projection(doc! {"_id": 0, "source": 0, "history": 0, "rate_history": {"$slice": -1}})
+        .sort(doc! {"id": 1})
+        .skip(pagination.skip())
+        .limit(pagination.limit() as i64)
+        .await?
+        .try_collect::<Vec<_>>().await?
+        .into_iter()
+        .map(|page| flatten_rating(page, false))
+        .collect();
+    Ok(Paged{items, page: pagination.page(), per_page: pagination.limit(), total})
+}
+
+async fn find_page(state: &ApiState, filter: Document) -> Result<Value, WikidotError>{
+    let page = state.page_docs()
+        .find_one(filter)
+        .projection(doc! {"_id": 0, "history": 0, "rate_history": {"$slice": -1}})
+        .await?
+        .ok_or(TargetNotExist::page())?;
+    Ok(flatten_rating(page, true))
+}
+
+// Pages an array field of a single page, e.g. its rate or revision history
+async fn page_array(state: &ApiState, id: i32, field: &str, pagination: &Pagination) -> Result<Paged, WikidotError>{
+    let pipeline = [
+        doc! {"$match": {"id": id}},
+        doc! {"$project": {
+            "_id": 0,
+            "total": {"$size": format!("${field}")},
+            "items": {"$slice": [format!("${field}"), pagination.skip() as i64, pagination.limit() as i64]},
+        }},
+        doc! {"$unset": "items.source"},
+    ];
+    let result = state.page_docs().aggregate(pipeline).await?
+        .try_next().await?
+        .ok_or(TargetNotExist::page())?;
+    let items = match result.get_array("items") {
+        Ok(items) => items.iter().cloned().map(to_json).collect(),
+        Err(_) => Vec::new(),
+    };
+    let total = result.get_i32("total").unwrap_or_default() as u64;
+    Ok(Paged{items, page: pagination.page(), per_page: pagination.limit(), total})
+}
+
+async fn list_pages(State(state): State<ApiState>, headers: HeaderMap, Query(filter): Query<PageFilter>) -> Result<Response, WikidotError>{
+    let paged = find_summaries(&state, filter.to_doc(), &filter.pagination()).await?;
+    Ok(with_etag(&headers, &paged))
+}
+
+async fn page_by_id(State(state): State<ApiState>, headers: HeaderMap, Path(id): Path<i32>) -> Result<Response, WikidotError>{
+    Ok(with_etag(&headers, &find_page(&state, doc! {"id": id}).await?))
+}
+
+// Deleted and recreated pages share a fullname, the live one wins
+async fn page_by_name(State(state): State<ApiState>, headers: HeaderMap, Path(fullname): Path<String>) -> Result<Response, WikidotError>{
+    let page = match find_page(&state, doc! {"fullname": &fullname, "status": true}).await {
+        Err(WikidotError::TargetNotExist(_)) => find_page(&state, doc! {"fullname": &fullname}).await?,
+        page => page?,
+    };
+    Ok(with_etag(&headers, &page))
+}
+
+// Older entries only hold the votes that changed after them, the last one holds every vote
+async fn page_rates(State(state): State<ApiState>, headers: HeaderMap, Path(id): Path<i32>, Query(pagination): Query<Pagination>) -> Result<Response, WikidotError>{
+    Ok(with_etag(&headers, &page_array(&state, id, "rate_history", &pagination).await?))
+}
+
+async fn page_revisions(State(state): State<ApiState>, headers: HeaderMap, Path(id): Path<i32>, Query(pagination): Query<Pagination>) -> Result<Response, WikidotError>{
+    Ok(with_etag(&headers, &page_array(&state, id, "history", &pagination).await?))
+}
+
+// Revisions are picked by their index and only render if their source was archived
+async fn page_html(State(state): State<ApiState>, Path(fullname): Path<String>, Query(query): Query<RevisionQuery>) -> Result<Response, WikidotError>{
+    let html = render_archived_page(state.pages.clone(), state.revisions.clone(), &fullname, query.revision, "/pages/name/", "/html", &state.file_base).await?
+        .ok_or(TargetNotExist::page())?;
+    Ok(Html(html).into_response())
+}
+
+async fn user_by_id(State(state): State<ApiState>, headers: HeaderMap, Path(id): Path<i32>) -> Result<Response, WikidotError>{
+    let user = state.user_docs()
+        .find_one(doc! {"id": id})
+        .projection(doc! {"_id": 0})
+        .await?
+        .ok_or(TargetNotExist::user())?;
+    Ok(with_etag(&headers, &to_json(Bson::Document(user))))
+}
+
+async fn user_pages(State(state): State<ApiState>, headers: HeaderMap, Path(id): Path<i32>, Query(pagination): Query<Pagination>) -> Result<Response, WikidotError>{
+    let paged = find_summaries(&state, doc! {"author": id}, &pagination).await?;
+    Ok(with_etag(&headers, &paged))
+}
+
+// Every vote the user has on record, oldest first, with its changes and removal
+async fn user_vote_list(State(state): State<ApiState>, headers: HeaderMap, Path(id): Path<i32>, Query(pagination): Query<Pagination>) -> Result<Response, WikidotError>{
+    let total = state.user_votes.count_documents(doc! {"user_id": id}).await?;
+    let items = state.user_votes.clone_with_type::<Document>()
+        .find(doc! {"user_id": id})
+        .projection(doc! {"_id": 0, "rate_entries": 0})
+        .sort(doc! {"first_seen": 1, "page_id": 1})
+        .skip(pagination.skip())
+        .limit(pagination.limit() as i64)
+        .await?
+        .map_ok(|vote| to_json(Bson::Document(vote)))
+        .try_collect().await?;
+    Ok(with_etag(&headers, &Paged{items, page: pagination.page(), per_page: pagination.limit(), total}))
+}
+
+async fn user_vote_summary(State(state): State<ApiState>, headers: HeaderMap, Path(id): Path<i32>) -> Result<Response, WikidotError>{
+    Ok(with_etag(&headers, &vote_summary(state.user_votes.clone(), id).await?))
+}
+
+pub fn router(state: ApiState) -> Router{
+    Router::new()
+        .route("/pages", get(list_pages))
+        .route("/pages/:id", get(page_by_id))
+        .route("/pages/:id/rates", get(page_rates))
+        .route("/pages/:id/revisions", get(page_revisions))
+        .route("/pages/name/:fullname", get(page_by_name))
+        .route("/pages/name/:fullname/html", get(page_html))
+        .route("/users/:id", get(user_by_id))
+        .route("/users/:id/pages", get(user_pages))
+        .route("/users/:id/votes", get(user_vote_list))
+        .route("/users/:id/votes/summary", get(user_vote_summary))
+        .with_state(state)
+}
@@ -0,0 +1,49 @@
+use std::error::Error;
+use clap::Parser;
+use tokio::net::TcpListener;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+use wikidot::{api_graphql, api_rest::{self, ApiState}, config::Config};
+
+#[derive(Parser)]
+#[command(about = "Read-only JSON and GraphQL API over the Backroomer archive")]
+struct Cli{
+    #[arg(short, long, default_value = "config.toml")]
+    config: String,
+    /// Site whose database is served, defaults to the first configured site
+    #[arg(long)]
+    site: Option<String>,
+    /// Overrides the database of the selected site
+    #[arg(long)]
+    db: Option<String>,
+    /// Overrides `api.listen` from the config
+    #[arg(long)]
+    listen: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>>{
+    let cli = Cli::parse();
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn,wikidot=info")))
+        .init();
+
+    let mut config = Config::load(&cli.config)?;
+    if let Some(listen) = cli.listen {
+        config.api.listen = listen;
+    }
+    config.validate_api()?;
+    // Only one site is served, so --db may replace the first one's database
+    let site = config.select_sites(cli.site.as_deref(), None)?.remove(0);
+    let database = cli.db.unwrap_or(site.database);
+
+    let mongo = mongodb::Client::with_uri_str(config.database.link.as_deref().unwrap_or_default()).await?;
+    let state = ApiState::new(&mongo.database(&database), &site.name);
+    let app = api_rest::router(state.clone()).merge(api_graphql::router(&state));
+    let listener = TcpListener::bind(&config.api.listen).await?;
+    info!(addr = %config.api.listen, database, "serving api");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async { let _ = tokio::signal::ctrl_c().await; })
+        .await?;
+    Ok(())
+}
@@ -1,17 +1,20 @@
-use std::time::Duration;
+use std::{collections::HashMap, future::Future, sync::Arc, time::{Duration, Instant}};
 use reqwest::{header::{HeaderMap, HeaderValue}, redirect::Policy, ClientBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
-use tokio::time::sleep;
+use tokio::{sync::{Mutex, OwnedSemaphorePermit, Semaphore}, time::sleep};
+use tracing::{debug, debug_span, warn, Instrument};
 
-use crate::error::{AjaxClientError, WikidotRespondError};
+use crate::{error::{AjaxClientError, WikidotRespondError}, metrics, user::User};
 
 const UA: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/108.0.0.0 Safari/537.36";
 const RF: &str = "wikidot.rs";
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
 pub struct AjaxConfig{
     pub attempt_limit: i8,
     pub retry_interval: i8,
+    // Pages of one listing (ListPages, members) requested at once, the request limit still caps the total
     pub semaphore_limit: i8,
     pub request_timeout: i8,
 }
@@ -20,6 +23,12 @@ pub struct AjaxConfig{
 pub struct AjaxClient{
     pub config: AjaxConfig,
     pub cookies: Option<String>,
+    // Shared by every clone, caps in-flight requests across all sites
+    #[serde(skip)]
+    pub limiter: Option<Arc<Semaphore>>,
+    // Users looked up by unix name, shared by every clone and dropped with the client
+    #[serde(skip)]
+    pub users: Arc<Mutex<HashMap<String, User>>>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -28,6 +37,40 @@ pub struct AjaxResponse{
     pub body: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct AjaxActionResponse{
+    pub status: String,
+    pub message: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+// Module or action a request targets, for logging
+fn module_name<'a>(param: &[(&str, &'a str)]) -> &'a str{
+    param.iter()
+        .rev()
+        .find(|(key, _)| *key == "moduleName" || *key == "action")
+        .map_or("", |(_, value)| *value)
+}
+
+fn record_attempt<T>(module: &str, started: Instant, result: &Result<T, AjaxClientError>){
+    metrics::REQUESTS.with_label_values(&[module]).inc();
+    metrics::REQUEST_DURATION.with_label_values(&[module]).observe(started.elapsed().as_secs_f64());
+    if let Err(AjaxClientError::WikidotRespondError(e)) = result {
+        if e.is_try_again() {
+            metrics::TRY_AGAIN.with_label_values(&[module]).inc();
+        }
+    }
+}
+
+fn log_attempt<T>(attempt: i8, started: Instant, http_status: Option<u16>, result: &Result<T, AjaxClientError>){
+    let latency_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok(_) => debug!(attempt, latency_ms, http_status, status = "ok", "request finished"),
+        Err(e) => warn!(attempt, latency_ms, http_status, status = %e, "request failed"),
+    }
+}
+
 impl Default for AjaxConfig{
     fn default() -> Self{
         AjaxConfig{
@@ -39,11 +82,19 @@ impl Default for AjaxConfig{
     }
 }
 
+impl Default for AjaxClient{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
 impl AjaxClient{
     pub fn new() -> Self{
         AjaxClient{
             config: AjaxConfig::default(),
             cookies: None,
+            limiter: None,
+            users: Arc::default(),
         }
     }
 
@@ -71,15 +122,29 @@ impl AjaxClient{
         Ok(AjaxClient{
             config: AjaxConfig::default(),
             cookies: Some(cookies),
+            limiter: None,
+            users: Arc::default(),
         })
     }
 
+    pub fn with_request_limit(mut self, limit: usize) -> Self{
+        self.limiter = Some(Arc::new(Semaphore::new(limit)));
+        self
+    }
+
+    async fn permit(&self) -> Option<OwnedSemaphorePermit>{
+        match &self.limiter {
+            Some(limiter) => limiter.clone().acquire_owned().await.ok(),
+            None => None,
+        }
+    }
+
     async fn process_post_response(value: Result<reqwest::Response, reqwest::Error>) -> Result<AjaxResponse, AjaxClientError>{
         let ajax = Self::process_response(value)?.json::<AjaxResponse>().await?;
         if &ajax.status == "try_again"{
             Err(WikidotRespondError::try_again())?
         }
-        else if &ajax.body == "" {
+        else if ajax.body.is_empty() {
             Err(WikidotRespondError::empty())?
         }
         else {
@@ -87,7 +152,17 @@ impl AjaxClient{
         }
     }
 
-    fn process_response(value: Result<reqwest::Response, reqwest::Error>) -> Result<Response, AjaxClientError>{
+    async fn process_action_response(value: Result<reqwest::Response, reqwest::Error>) -> Result<AjaxActionResponse, AjaxClientError>{
+        let ajax = Self::process_response(value)?.json::<AjaxActionResponse>().await?;
+        if &ajax.status == "try_again"{
+            Err(WikidotRespondError::try_again())?
+        }
+        else {
+            Ok(ajax)
+        }
+    }
+
+    pub(crate) fn process_response(value: Result<reqwest::Response, reqwest::Error>) -> Result<Response, AjaxClientError>{
         let response = value?;
 
         let status = response.status();
@@ -104,7 +179,7 @@ impl AjaxClient{
         headers.insert("user-agent", HeaderValue::from_str(UA)?);
         headers.insert("referer", HeaderValue::from_str(RF)?);
         if let Some(cookies) = &self.cookies {
-            headers.insert("cookie", HeaderValue::from_str(&cookies)?);
+            headers.insert("cookie", HeaderValue::from_str(cookies)?);
         }
         else {
             headers.insert("cookie", HeaderValue::from_str("wikidot_token7=123456")?);
@@ -117,41 +192,59 @@ impl AjaxClient{
             .build()?)
     }
     
-    pub async fn request(&self, param: &[(&str, &str)], url: &str) -> Result<AjaxResponse, AjaxClientError>{
+    // Holds a permit only while a request is in flight, retries failed attempts after retry_interval
+    // and records each attempt under `module`; `decode` decides what counts as a failure
+    pub(crate) async fn send_with_retry<T, B, D, F>(&self, module: &str, url: &str, build: B, decode: D) -> Result<T, AjaxClientError>
+    where
+        B: Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+        D: Fn(Result<Response, reqwest::Error>) -> F,
+        F: Future<Output = Result<T, AjaxClientError>>,
+    {
         let mut attempt: i8 = 0;
+        async {
+            loop {
+                let permit = self.permit().await;
+                let started = Instant::now();
+                let response = build(&self.client().await?).send().await;
+                let http_status = response.as_ref().ok().map(|response| response.status().as_u16());
+                let processed = decode(response).await;
+                drop(permit);
+                record_attempt(module, started, &processed);
+                log_attempt(attempt, started, http_status, &processed);
+
+                if processed.is_ok() || attempt > self.config.attempt_limit {
+                    return processed
+                }
+
+                attempt += 1;
+                metrics::REQUEST_RETRIES.with_label_values(&[module]).inc();
+                sleep(Duration::from_secs(self.config.retry_interval as u64)).await;
+            }
+        }
+        .instrument(debug_span!("request", module, url))
+        .await
+    }
+
+    pub async fn request(&self, param: &[(&str, &str)], url: &str) -> Result<AjaxResponse, AjaxClientError>{
         let mut param_vec = Vec::from([
             ("callbackIndex", "0"), 
             ("wikidot_token7", "123456")
         ]);
         param_vec.extend_from_slice(param);
-        loop {
-            let response = self.client().await?.post(url)
-                .form(param_vec.as_slice())
-                .send().await;
-
-            let processed = Self::process_post_response(response).await;
-
-            if processed.is_ok() || attempt > self.config.attempt_limit {
-                return processed
-            }
+        self.send_with_retry(module_name(param), url, |client| client.post(url).form(param_vec.as_slice()), Self::process_post_response).await
+    }
 
-            attempt += 1;
-            sleep(Duration::from_secs(self.config.retry_interval as u64)).await;
-        }
+    pub async fn action(&self, param: &[(&str, &str)], url: &str) -> Result<AjaxActionResponse, AjaxClientError>{
+        let mut param_vec = Vec::from([
+            ("callbackIndex", "0"),
+            ("wikidot_token7", "123456"),
+            ("moduleName", "Empty"),
+        ]);
+        param_vec.extend_from_slice(param);
+        self.send_with_retry(module_name(param), url, |client| client.post(url).form(param_vec.as_slice()), Self::process_action_response).await
     }
 
     pub async fn get(&self, url: &str) -> Result<Response, AjaxClientError>{
-        let mut attempt: i8 = 0;
-        loop {
-            let response = self.client().await?.get(url).send().await;
-            let processed = Self::process_response(response);
-
-            if processed.is_ok() || attempt > self.config.attempt_limit {
-                return processed
-            }
-
-            attempt += 1;
-            sleep(Duration::from_secs(self.config.retry_interval as u64)).await;
-        }
+        self.send_with_retry("GET", url, |client| client.get(url), |response| async { Self::process_response(response) }).await
     }
 }
\ No newline at end of file
@@ -0,0 +1,350 @@
+use std::{collections::HashSet, fs, net::SocketAddr, path::Path, str::FromStr};
+use chrono::{TimeZone, Utc};
+use cron::Schedule;
+use mongodb::bson::DateTime;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::{client::AjaxConfig, error::ConfigError};
+
+static SITE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-z0-9-]+$").unwrap());
+
+const ALT_TITLE_URLS: [&str; 12] = [
+    "https://backrooms-wiki-cn.wikidot.com/normal-levels-i",
+    "https://backrooms-wiki-cn.wikidot.com/sub-layers",
+    "https://backrooms-wiki-cn.wikidot.com/enigmatic-levels",
+    "https://backrooms-wiki-cn.wikidot.com/objects",
+    "https://backrooms-wiki-cn.wikidot.com/phenomena",
+    "https://backrooms-wiki-cn.wikidot.com/normal-levels-cn-i",
+    "https://backrooms-wiki-cn.wikidot.com/normal-levels-cn-ii",
+    "https://backrooms-wiki-cn.wikidot.com/sub-layers-cn",
+    "https://backrooms-wiki-cn.wikidot.com/enigmatic-series-cn",
+    "https://backrooms-wiki-cn.wikidot.com/entities-cn",
+    "https://backrooms-wiki-cn.wikidot.com/objects-cn",
+    "https://backrooms-wiki-cn.wikidot.com/phenomena-cn",
+];
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config{
+    pub site: String,
+    // Leave empty to crawl only `site` into `database.name`
+    pub sites: Vec<SiteConfig>,
+    pub database: DatabaseConfig,
+    pub account: AccountConfig,
+    pub crawler: CrawlerConfig,
+    pub ajax: AjaxConfig,
+    pub api: ApiConfig,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig{
+    pub link: Option<String>,
+    pub name: String,
+}
+
+// Empty or zero fields fall back to the crawler settings, and the database to the site name
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig{
+    pub name: String,
+    pub database: String,
+    pub attribution_url: String,
+    pub alt_title_urls: Vec<String>,
+    pub sleep_interval: u64,
+    pub full_crawl_interval: u64,
+    // Cron expression with seconds, e.g. "0 0 */6 * * *", used instead of sleep_interval
+    pub schedule: String,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountConfig{
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlerConfig{
+    // Requests in flight across all sites, and pages each run stage works on at once
+    pub concurrency: usize,
+    pub sleep_interval: u64,
+    pub full_crawl_interval: u64,
+    pub schedule: Option<String>,
+    pub ignored_users: Vec<i32>,
+    // Defaults to the site's attribution-metadata page
+    pub attribution_url: Option<String>,
+    pub alt_title_urls: Vec<String>,
+    // Address the daemon serves /metrics on, e.g. "127.0.0.1:9898"
+    pub metrics_listen: Option<String>,
+    // Every vote_poll_interval seconds the daemon polls the votes of vote_poll_budget pages, 0 turns it off
+    pub vote_poll_interval: u64,
+    pub vote_poll_budget: usize,
+    // Sources of older revisions fetched per run, 0 turns the backfill off
+    pub revision_backfill_budget: usize,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig{
+    pub listen: String,
+}
+
+// Empty entries in .env files don't override the config
+fn env_var(key: &str) -> Option<String>{
+    dotenv::var(key).ok().filter(|val| !val.trim().is_empty())
+}
+
+impl Default for Config{
+    fn default() -> Self{
+        Config{
+            site: String::from("backrooms-wiki-cn"),
+            sites: Vec::new(),
+            database: DatabaseConfig::default(),
+            account: AccountConfig::default(),
+            crawler: CrawlerConfig::default(),
+            ajax: AjaxConfig::default(),
+            api: ApiConfig::default(),
+        }
+    }
+}
+
+impl Default for ApiConfig{
+    fn default() -> Self{
+        ApiConfig{listen: String::from("127.0.0.1:8080")}
+    }
+}
+
+impl Default for DatabaseConfig{
+    fn default() -> Self{
+        DatabaseConfig{
+            link: None,
+            name: String::from("backrooms-cn"),
+        }
+    }
+}
+
+impl Default for CrawlerConfig{
+    fn default() -> Self{
+        CrawlerConfig{
+            concurrency: 5,
+            sleep_interval: 21600,
+            full_crawl_interval: 86400,
+            schedule: None,
+            ignored_users: vec![8528464],
+            attribution_url: None,
+            alt_title_urls: ALT_TITLE_URLS.map(String::from).to_vec(),
+            metrics_listen: None,
+            vote_poll_interval: 600,
+            vote_poll_budget: 50,
+            revision_backfill_budget: 200,
+        }
+    }
+}
+
+impl Config{
+    // Reads the file if it exists, then applies env overrides from the environment and .env.local,
+    // validation is left to the caller since the crawler and the API need different settings
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError>{
+        let path = path.as_ref();
+        let mut config = if path.exists() {
+            let content = fs::read_to_string(path).map_err(|_| ConfigError::read())?;
+            toml::from_str::<Config>(&content).map_err(ConfigError::parse)?
+        }
+        else {
+            Config::default()
+        };
+        let _ = dotenv::from_filename(".env.local");
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError>{
+        if let Some(val) = env_var("DB_LINK") {
+            self.database.link = Some(val);
+        }
+        if let Some(val) = env_var("WD_USERNAME") {
+            self.account.username = Some(val);
+        }
+        if let Some(val) = env_var("WD_PASSWORD") {
+            self.account.password = Some(val);
+        }
+        if let Some(val) = env_var("SEMAPHORE") {
+            self.crawler.concurrency = val.parse().map_err(|_| ConfigError::invalid("SEMAPHORE", "must be a positive integer"))?;
+        }
+        if let Some(val) = env_var("METRICS_LISTEN") {
+            self.crawler.metrics_listen = Some(val);
+        }
+        if let Some(val) = env_var("FULL_CRAWL_INTERVAL") {
+            self.crawler.full_crawl_interval = val.parse().map_err(|_| ConfigError::invalid("FULL_CRAWL_INTERVAL", "must be a number of seconds"))?;
+        }
+        Ok(())
+    }
+
+    // Everything the crawler needs, run after command line overrides are applied
+    pub fn validate(&self) -> Result<(), ConfigError>{
+        if !SITE_RE.is_match(&self.site) {
+            Err(ConfigError::invalid("site", "must be a wikidot site name such as backrooms-wiki-cn"))?
+        }
+        let mut names = HashSet::new();
+        let mut databases = HashSet::new();
+        for (i, site) in self.sites().iter().enumerate(){
+            if !SITE_RE.is_match(&site.name) {
+                Err(ConfigError::invalid(&format!("sites[{i}].name"), "must be a wikidot site name such as backrooms-wiki-cn"))?
+            }
+            if !names.insert(&site.name) {
+                Err(ConfigError::invalid(&format!("sites[{i}].name"), "is listed more than once"))?
+            }
+            // Deletion marking covers the whole pages collection, so sites cannot share one
+            if !databases.insert(&site.database) {
+                Err(ConfigError::invalid(&format!("sites[{i}].database"), "is already used by another site"))?
+            }
+            if !site.schedule.is_empty() && Schedule::from_str(&site.schedule).is_err() {
+                Err(ConfigError::invalid(&format!("sites[{i}].schedule"), "must be a cron expression with seconds, e.g. \"0 0 */6 * * *\""))?
+            }
+            if !site.attribution_url.starts_with("http") {
+                Err(ConfigError::invalid(&format!("sites[{i}].attribution_url"), "must be an http(s) url"))?
+            }
+            for (j, url) in site.alt_title_urls.iter().enumerate(){
+                if !url.starts_with("http") {
+                    Err(ConfigError::invalid(&format!("sites[{i}].alt_title_urls[{j}]"), "must be an http(s) url"))?
+                }
+            }
+        }
+        if self.database.name.is_empty() {
+            Err(ConfigError::invalid("database.name", "must not be empty"))?
+        }
+        if self.database.link.as_deref().is_none_or(str::is_empty) {
+            Err(ConfigError::invalid("database.link", "is missing, set it in the config or DB_LINK"))?
+        }
+        if self.account.username.as_deref().is_none_or(str::is_empty) {
+            Err(ConfigError::invalid("account.username", "is missing, set it in the config or WD_USERNAME"))?
+        }
+        if self.account.password.as_deref().is_none_or(str::is_empty) {
+            Err(ConfigError::invalid("account.password", "is missing, set it in the config or WD_PASSWORD"))?
+        }
+        if self.crawler.concurrency == 0 {
+            Err(ConfigError::invalid("crawler.concurrency", "must be greater than 0"))?
+        }
+        if self.crawler.sleep_interval == 0 {
+            Err(ConfigError::invalid("crawler.sleep_interval", "must be greater than 0"))?
+        }
+        if self.crawler.vote_poll_budget > 0 && self.crawler.vote_poll_interval == 0 {
+            Err(ConfigError::invalid("crawler.vote_poll_interval", "must be greater than 0 while vote_poll_budget is set"))?
+        }
+        if let Some(url) = &self.crawler.attribution_url {
+            if !url.starts_with("http") {
+                Err(ConfigError::invalid("crawler.attribution_url", "must be an http(s) url"))?
+            }
+        }
+        for (i, url) in self.crawler.alt_title_urls.iter().enumerate(){
+            if !url.starts_with("http") {
+                Err(ConfigError::invalid(&format!("crawler.alt_title_urls[{i}]"), "must be an http(s) url"))?
+            }
+        }
+        if let Some(addr) = &self.crawler.metrics_listen {
+            if addr.parse::<SocketAddr>().is_err() {
+                Err(ConfigError::invalid("crawler.metrics_listen", "must be an address such as 127.0.0.1:9898"))?
+            }
+        }
+        if self.ajax.attempt_limit <= 0 {
+            Err(ConfigError::invalid("ajax.attempt_limit", "must be greater than 0"))?
+        }
+        if self.ajax.retry_interval < 0 {
+            Err(ConfigError::invalid("ajax.retry_interval", "must not be negative"))?
+        }
+        if self.ajax.semaphore_limit <= 0 {
+            Err(ConfigError::invalid("ajax.semaphore_limit", "must be greater than 0"))?
+        }
+        if self.ajax.request_timeout <= 0 {
+            Err(ConfigError::invalid("ajax.request_timeout", "must be greater than 0"))?
+        }
+        Ok(())
+    }
+
+    // The API only reads the archive, so it needs no account or crawler settings
+    pub fn validate_api(&self) -> Result<(), ConfigError>{
+        if self.database.link.as_deref().is_none_or(str::is_empty) {
+            Err(ConfigError::invalid("database.link", "is missing, set it in the config or DB_LINK"))?
+        }
+        if self.api.listen.parse::<SocketAddr>().is_err() {
+            Err(ConfigError::invalid("api.listen", "must be an address such as 127.0.0.1:8080"))?
+        }
+        Ok(())
+    }
+
+    pub fn sites(&self) -> Vec<SiteConfig>{
+        if self.sites.is_empty() {
+            vec![self.resolve_site(&self.site)]
+        }
+        else {
+            self.sites.iter().map(|site| self.resolve_site(&site.name)).collect()
+        }
+    }
+
+    // The site picked on the command line, whose database `database` replaces, or every configured site;
+    // a database only ever belongs to one site, so it can't replace the databases of several
+    pub fn select_sites(&self, name: Option<&str>, database: Option<&str>) -> Result<Vec<SiteConfig>, ConfigError>{
+        let Some(name) = name else {
+            if database.is_some() {
+                Err(ConfigError::invalid("--db", "needs --site, every site keeps its own database"))?
+            }
+            return Ok(self.sites())
+        };
+        if !SITE_RE.is_match(name) {
+            Err(ConfigError::invalid("--site", "must be a wikidot site name such as backrooms-wiki-cn"))?
+        }
+        let mut site = self.resolve_site(name);
+        if let Some(database) = database {
+            site.database = database.to_string();
+        }
+        Ok(vec![site])
+    }
+
+    // Fills in defaults for a configured site, or describes an unlisted one the way `site` is;
+    // only `site` itself keeps `database.name`, other unlisted sites get a database of their own
+    pub fn resolve_site(&self, name: &str) -> SiteConfig{
+        let mut site = self.sites.iter()
+            .find(|site| site.name == name)
+            .cloned()
+            .unwrap_or_else(|| SiteConfig{
+                name: name.to_string(),
+                database: if name == self.site {self.database.name.clone()} else {String::new()},
+                alt_title_urls: if name == self.site {self.crawler.alt_title_urls.clone()} else {Vec::new()},
+                ..SiteConfig::default()
+            });
+        if site.database.is_empty() {
+            site.database = site.name.clone();
+        }
+        if site.attribution_url.is_empty() {
+            site.attribution_url = match &self.crawler.attribution_url {
+                Some(url) if name == self.site => url.clone(),
+                _ => format!("https://{}.wikidot.com/attribution-metadata", site.name),
+            };
+        }
+        if site.sleep_interval == 0 {
+            site.sleep_interval = self.crawler.sleep_interval;
+        }
+        if site.full_crawl_interval == 0 {
+            site.full_crawl_interval = self.crawler.full_crawl_interval;
+        }
+        if site.schedule.is_empty() {
+            site.schedule = self.crawler.schedule.clone().unwrap_or_default();
+        }
+        site
+    }
+}
+
+impl SiteConfig{
+    pub fn next_run(&self, after: DateTime) -> DateTime{
+        let next = Schedule::from_str(&self.schedule).ok()
+            .zip(Utc.timestamp_millis_opt(after.timestamp_millis()).single())
+            .and_then(|(schedule, after)| schedule.after(&after).next());
+        match next {
+            Some(next) => DateTime::from_millis(next.timestamp_millis()),
+            None => DateTime::from_millis(after.timestamp_millis() + self.sleep_interval as i64 * 1000),
+        }
+    }
+}
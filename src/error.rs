@@ -28,6 +28,10 @@ macro_rules! define_error {
                 Self { kind: kind.to_string(), message: message.to_string() }
             }
 
+            pub fn kind(&self) -> &str {
+                &self.kind
+            }
+
             $(
                 pub fn $variant() -> Self {
                     Self::new($kind, $message)
@@ -50,10 +54,16 @@ define_error!(ParseElementError,
     site_ele => ("Site", "Element out of bound"),
     parser_id => ("Parser", "Cannot get id from the element"),
     parser_unix_name => ("Parser", "Cannot get unix name from the element"),
+    parser_ip => ("Parser", "Cannot get ip address from the element"),
     user_date => ("User", "Cannot get joined date from the element"),
     user_ele => ("User", "Element out of bound"),
     user_avatar => ("User", "Cannot get avatar url from the element"),
+    user_id => ("User", "Cannot get user id from the element"),
     mongo_ele => ("Mongodb", "Element out of bound"),
+    file_id => ("File", "Cannot get file id from the element"),
+    file_ele => ("File", "Element out of bound"),
+    file_status => ("File", "Cannot get upload status from the response"),
+    change_ele => ("Change", "Element out of bound"),
 );
 
 define_error!(IdNotFound,
@@ -64,6 +74,14 @@ define_error!(IdNotFound,
 define_error!(TargetNotExist,
     site => ("Site", "Site not found"),
     page => ("Page", "Page not found"),
+    user => ("User", "User not found"),
+);
+
+define_error!(FileActionError,
+    exists => ("File", "File already exists on the page"),
+    not_found => ("File", "File not found on the page"),
+    empty => ("File", "File is empty"),
+    too_large => ("File", "File exceeds the upload size limit"),
 );
 
 define_error!(WikidotRespondError,
@@ -75,6 +93,25 @@ impl WikidotRespondError {
     pub fn status(status: reqwest::StatusCode) -> Self {
         Self::new("status", status.as_str())
     }
+
+    pub fn is_try_again(&self) -> bool {
+        self.message == Self::try_again().message
+    }
+}
+
+define_error!(ConfigError,
+    read => ("config", "Cannot read the config file"),
+);
+
+impl ConfigError {
+    // The kind names the offending key, e.g. "[crawler.concurrency] must be greater than 0"
+    pub fn invalid(key: &str, message: &str) -> Self {
+        Self::new(key, message)
+    }
+
+    pub fn parse(error: toml::de::Error) -> Self {
+        Self::new("config", error.to_string().trim())
+    }
 }
 
 #[derive(Debug)]
@@ -124,6 +161,7 @@ pub enum WikidotError {
     ClientError(AjaxClientError),
     IdNotFound(IdNotFound),
     TargetNotExist(TargetNotExist),
+    FileActionError(FileActionError),
     SerdeJsonError(serde_json::Error),
     MongodbError(mongodb::error::Error),
 }
@@ -138,6 +176,7 @@ impl Display for WikidotError {
             Self::ClientError(e) => write!(f, "Client error: {}", e),
             Self::IdNotFound(e) => write!(f, "ID not found: {}", e),
             Self::TargetNotExist(e) => write!(f, "Target not exist: {}", e),
+            Self::FileActionError(e) => write!(f, "File action error: {}", e),
             Self::SerdeJsonError(e) => write!(f, "JSON parse error: {}", e),
             Self::MongodbError(e) => write!(f, "MongoDB error: {}", e),
         }
@@ -146,6 +185,25 @@ impl Display for WikidotError {
 
 impl std::error::Error for WikidotError {}
 
+impl WikidotError {
+    // Short label for grouping errors, e.g. "TargetNotExist/Page"
+    pub fn kind(&self) -> String {
+        match self {
+            Self::ParseRegexError(_) => String::from("ParseRegexError"),
+            Self::ParseIntError(_) => String::from("ParseIntError"),
+            Self::ParseFloatError(_) => String::from("ParseFloatError"),
+            Self::ParseElementError(e) => format!("ParseElementError/{}", e.kind()),
+            Self::ClientError(AjaxClientError::WikidotRespondError(e)) => format!("ClientError/{}", e.kind()),
+            Self::ClientError(_) => String::from("ClientError"),
+            Self::IdNotFound(e) => format!("IdNotFound/{}", e.kind()),
+            Self::TargetNotExist(e) => format!("TargetNotExist/{}", e.kind()),
+            Self::FileActionError(e) => format!("FileActionError/{}", e.kind()),
+            Self::SerdeJsonError(_) => String::from("SerdeJsonError"),
+            Self::MongodbError(_) => String::from("MongodbError"),
+        }
+    }
+}
+
 // From implementations for WikidotError
 impl From<reqwest::Error> for WikidotError {
     fn from(value: reqwest::Error) -> Self { Self::ClientError(AjaxClientError::from(value)) }
@@ -163,8 +221,12 @@ impl From<regex::Error> for WikidotError {
     fn from(value: regex::Error) -> Self { Self::ParseRegexError(value) }
 }
 
+// Parse errors are counted here, where they actually surface rather than where `ok_or` builds them
 impl From<ParseElementError> for WikidotError {
-    fn from(value: ParseElementError) -> Self { Self::ParseElementError(value) }
+    fn from(value: ParseElementError) -> Self {
+        crate::metrics::PARSE_ERRORS.with_label_values(&[value.kind()]).inc();
+        Self::ParseElementError(value)
+    }
 }
 
 impl From<ParseIntError> for WikidotError {
@@ -183,6 +245,10 @@ impl From<TargetNotExist> for WikidotError {
     fn from(value: TargetNotExist) -> Self { Self::TargetNotExist(value) }
 }
 
+impl From<FileActionError> for WikidotError {
+    fn from(value: FileActionError) -> Self { Self::FileActionError(value) }
+}
+
 impl From<serde_json::Error> for WikidotError {
     fn from(value: serde_json::Error) -> Self { Self::SerdeJsonError(value) }
 }
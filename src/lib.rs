@@ -4,8 +4,27 @@ pub mod page;
 pub mod user;
 pub mod page_history;
 pub mod page_rate;
+pub mod page_file;
 pub mod parser;
 pub mod selectors;
 pub mod error;
+pub mod config;
 pub mod mongo_page;
-pub mod mongo_user;
\ No newline at end of file
+pub mod mongo_user;
+pub mod site_member;
+pub mod site_changes;
+pub mod wikitext;
+pub mod page_links;
+pub mod page_text;
+pub mod page_html;
+pub mod mongo_run;
+pub mod mongo_failure;
+pub mod mongo_links;
+pub mod mongo_member;
+pub mod mongo_votes;
+pub mod mongo_poll;
+pub mod mongo_revision;
+pub mod vote_report;
+pub mod metrics;
+pub mod api_rest;
+pub mod api_graphql;
\ No newline at end of file
@@ -1,24 +1,15 @@
-use std::{collections::HashMap, thread::sleep, time::Duration};
+use std::{collections::{HashMap, HashSet}, error::Error, sync::Mutex};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use futures::{stream, StreamExt};
 use mongodb::bson::{doc, DateTime};
 use scraper::{ElementRef, Html};
-use dotenv;
-use wikidot::{client::AjaxClient, error::{ParseElementError, WikidotError}, mongo_page::{update_alt_titles, update_page, MongoPage}, mongo_user::{add_user, update_user, MongoUser, USER_ADD, USER_NOW}, page::PAGE_VEC, parser, selectors, site::Site};
-
-const ALT_TITLE_URLS: [&str; 12] = [
-    "https://backrooms-wiki-cn.wikidot.com/normal-levels-i",
-    "https://backrooms-wiki-cn.wikidot.com/sub-layers",
-    "https://backrooms-wiki-cn.wikidot.com/enigmatic-levels",
-    "https://backrooms-wiki-cn.wikidot.com/objects",
-    "https://backrooms-wiki-cn.wikidot.com/phenomena",
-    "https://backrooms-wiki-cn.wikidot.com/normal-levels-cn-i",
-    "https://backrooms-wiki-cn.wikidot.com/normal-levels-cn-ii",
-    "https://backrooms-wiki-cn.wikidot.com/sub-layers-cn",
-    "https://backrooms-wiki-cn.wikidot.com/enigmatic-series-cn",
-    "https://backrooms-wiki-cn.wikidot.com/entities-cn",
-    "https://backrooms-wiki-cn.wikidot.com/objects-cn",
-    "https://backrooms-wiki-cn.wikidot.com/phenomena-cn",
-];
+use tokio::{net::TcpListener, signal::unix::{signal, SignalKind}, sync::watch};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use tracing_subscriber::EnvFilter;
+use wikidot::{client::AjaxClient, config::{Config, SiteConfig}, error::{ParseElementError, TargetNotExist, WikidotError}, metrics::{self, serve_metrics}, mongo_failure::{due_failures, failure_report, record_failure, resolve_failures, JobKind, MongoFailure}, mongo_links::{link_report, pages_including, rebuild_links, what_links_here, MongoLinks}, mongo_member::{update_members, MongoMember}, mongo_votes::{rebuild_user_votes, user_votes, vote_summary, MongoUserVote}, mongo_page::{filter_stale_pages, mark_deleted_pages, update_alt_titles, update_page, MongoPage}, mongo_poll::poll_votes, mongo_revision::{backfill_revision_sources, create_revision_indexes, MongoRevisionSource}, mongo_run::{checkpoint_pages, checkpoint_users, last_completed_run, save_run, unfinished_run, MongoRun, RunStatus, STAGES}, mongo_user::{add_user, update_user, MongoUser, USER_ADD, USER_NOW}, page::{Page, PAGE_VEC}, page_links::to_fullname, parser, selectors, site::Site, vote_report::vote_report};
+
+// Pages or users finished between checkpoint writes
+const CHECKPOINT_BATCH: usize = 50;
 
 macro_rules! collect_result {
     ($hash: expr, $results: expr, $iter: expr) => {
@@ -31,137 +22,811 @@ macro_rules! collect_result {
     };
 }
 
+#[derive(Parser)]
+#[command(about = "Backroomer crawler for Wikidot sites")]
+struct Cli{
+    #[arg(short, long, global = true, default_value = "config.toml")]
+    config: String,
+    /// Site to work on, defaults to the first configured site (the daemon runs all of them)
+    #[arg(long, global = true)]
+    site: Option<String>,
+    /// Overrides the database of the selected site
+    #[arg(long, global = true)]
+    db: Option<String>,
+    /// Overrides `crawler.concurrency` from the config
+    #[arg(short = 'j', long, global = true)]
+    concurrency: Option<usize>,
+    /// Raise the log level to debug (-v) or trace (-vv), RUST_LOG takes precedence
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat{
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command{
+    /// Refresh every page and mark missing ones deleted
+    CrawlPages{
+        /// Only refresh pages changed in the last N hours, without deletion marking
+        #[arg(long)]
+        since_hours: Option<u64>,
+    },
+    /// Refresh known users and add newly seen ones
+    CrawlUsers,
+    /// Apply co-author data from attribution-metadata
+    SyncAuthors,
+    /// Refresh alternative titles from the series hubs
+    SyncAltTitles,
+    /// Refresh the site membership roster
+    SyncMembers,
+    /// Rebuild the link graph and print the link report
+    SyncLinks,
+    /// List archived pages linking to or including a page, as of the last link sync
+    Backlinks{fullname: String},
+    /// Fetch the sources of archived revisions that have none yet, newest first
+    BackfillRevisions{
+        /// Overrides `crawler.revision_backfill_budget`
+        #[arg(long)]
+        budget: Option<usize>,
+    },
+    /// Rebuild per-user vote histories from the archived rate histories
+    SyncUserVotes,
+    /// Print a user's vote summary and every vote they cast
+    UserVotes{id: i32},
+    /// Print vote manipulation findings, most suspicious first
+    VoteReport{
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        /// Print the findings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Poll the votes of the most overdue pages once, hot pages first
+    PollVotes{
+        /// Overrides `crawler.vote_poll_budget`
+        #[arg(long)]
+        budget: Option<usize>,
+    },
+    /// Retry queued page and user jobs that are due
+    RetryFailures,
+    /// List jobs that exhausted their retries
+    FailureReport,
+    /// Refresh a single page
+    Page{fullname: String},
+    /// Refresh or add a single user
+    User{id: i32},
+    /// Run every stage for every site on its own schedule
+    RunDaemon{
+        /// Overrides the sleep interval of every site
+        #[arg(long)]
+        interval: Option<u64>,
+        /// Overrides the full sweep interval of every site
+        #[arg(long)]
+        full_interval: Option<u64>,
+    },
+}
+
+struct Crawler{
+    client: AjaxClient,
+    site: SiteConfig,
+    ignored_users: Vec<i32>,
+    concurrency: usize,
+    vote_poll_interval: u64,
+    vote_poll_budget: usize,
+    revision_backfill_budget: usize,
+    page_col: mongodb::Collection<MongoPage>,
+    user_col: mongodb::Collection<MongoUser>,
+    member_col: mongodb::Collection<MongoMember>,
+    link_col: mongodb::Collection<MongoLinks>,
+    vote_col: mongodb::Collection<MongoUserVote>,
+    revision_col: mongodb::Collection<MongoRevisionSource>,
+    // Voters found by poll rounds, added to this site's users by its next run
+    new_voters: Mutex<HashSet<i32>>,
+    failure_col: mongodb::Collection<MongoFailure>,
+    run_col: mongodb::Collection<MongoRun>,
+}
+
 async fn acquire_metadata(
     tr: ElementRef<'_>, 
-    site: Site,
-    page_col: mongodb::Collection<MongoPage>
+    client: AjaxClient,
+    page_col: mongodb::Collection<MongoPage>,
+    ignored_users: &[i32],
 ) -> Result<(), WikidotError> {
     let mut tds = tr.select(&selectors::TD);
     let page_fullname = tds.next().ok_or(ParseElementError::page_ele())?.text().collect::<String>();
     let user_name = tds.next().ok_or(ParseElementError::user_ele())?.text().collect::<String>();
     if user_name.is_empty() {return  Ok(())}
-    let user_res = site.request(&[
-        ("threadId", "15081869"),
-        ("source", format!("[[*user {}]]", &user_name).as_str()),
-        ("moduleName", "forum/ForumPreviewPostModule"),
-        ("parentId", ""),
-        ("title", "")
-    ]).await?;
-    let user_html = Html::parse_fragment(&user_res.body);
-
-    if let Some(user_ele) = user_html.select(&selectors::PRINTUSER).nth(1){
-        match parser::printuser(user_ele)?.id {
-            None => {return Ok(())},
-            Some(8528464) => {return Ok(())},
-            Some(data_id) => {
-                println!("{}, {}", page_fullname, user_name);
-                page_col.update_one(doc! {"fullname": page_fullname, "author": {"$ne": data_id}}, 
-                doc! { "$push": { "author": data_id } }).await?;
-            },
-        }
+
+    match client.user_by_name(&user_name).await?.id {
+        None => {return Ok(())},
+        Some(data_id) if ignored_users.contains(&data_id) => {return Ok(())},
+        Some(data_id) => {
+            debug!(page = %page_fullname, user = %user_name, "adding co-author");
+            parser::user_add(data_id);
+            page_col.update_one(doc! {"fullname": page_fullname, "author": {"$ne": data_id}}, 
+            doc! { "$push": { "author": data_id } }).await?;
+        },
     }
 
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>>{
-    dotenv::from_filename(".env.local")?;
-    let mongo = mongodb::Client::with_uri_str(dotenv::var("DB_LINK")?)
-        .await?;
-    let db = mongo.database("backrooms-cn");
-    let page_col: mongodb::Collection<MongoPage> = db.collection("pages");
-    let user_col: mongodb::Collection<MongoUser> = db.collection("users");
-    let client = AjaxClient::from(&dotenv::var("WD_USERNAME")?,
-        &dotenv::var("WD_PASSWORD")?).await?;
-    let semaphore = dotenv::var("SEMAPHORE")?.parse::<usize>()?;
-    loop {
-        let start = DateTime::now();
-        println!("start: {:?}", start.timestamp_millis());
+async fn find_page(site: &Site, fullname: &str) -> Result<Page, WikidotError>{
+    Ok(site.search(&[("fullname", fullname)]).await?
+        .into_iter()
+        .find(|page| page.fullname == fullname)
+        .ok_or(TargetNotExist::page())?)
+}
 
+impl Crawler{
+    // Restores users discovered before a resumed run stopped, and voters queued by poll rounds since
+    async fn load_users(&self, run: Option<&MongoRun>) -> Result<(), Box<dyn Error>>{
         USER_ADD.lock()?.clear();
         USER_NOW.lock()?.clear();
         PAGE_VEC.lock()?.clear();
 
-        for user_bson in user_col.distinct("id", doc! {}).await?{
+        for user_bson in self.user_col.distinct("id", doc! {}).await?{
             USER_NOW.lock()?.push(user_bson.as_i32().unwrap());
         }
-        let site = client.get_site("backrooms-wiki-cn").await?;
+        let known = USER_NOW.lock()?.iter().copied().collect::<HashSet<_>>();
+        let voters = self.new_voters.lock().unwrap().drain().collect::<Vec<_>>();
+        let mut user_add = USER_ADD.lock()?;
+        for user_id in run.iter().flat_map(|run| run.users_found.iter().copied()).chain(voters){
+            if !known.contains(&user_id) && !user_add.contains(&user_id) {
+                user_add.push(user_id);
+            }
+        }
+        Ok(())
+    }
+
+    async fn checkpoint_pages(&self, run: &mut MongoRun, batch: &mut Vec<String>) -> Result<(), Box<dyn Error>>{
+        let page_ids = PAGE_VEC.lock()?.clone();
+        let users_found = USER_ADD.lock()?.clone();
+        checkpoint_pages(self.run_col.clone(), run, batch, &page_ids, &users_found).await?;
+        resolve_failures(self.failure_col.clone(), JobKind::UpdatePage, batch).await?;
+        batch.clear();
+        Ok(())
+    }
+
+    async fn crawl_pages(&self, site: &Site, run: &mut MongoRun) -> Result<HashMap<String, WikidotError>, Box<dyn Error>>{
+        let mut pages = site.search(&[("category", "*")]).await?;
+        let listed = pages.iter().map(|page| page.fullname.clone()).collect::<Vec<_>>();
+        if let Some(since) = run.since {
+            let changed = site.recent_changes(since).await?
+                .into_iter()
+                .map(|change| change.fullname)
+                .collect::<HashSet<_>>();
+            pages = filter_stale_pages(self.page_col.clone(), pages, &changed).await?;
+        }
+        let done = run.pages_done.iter().cloned().collect::<HashSet<_>>();
+        pages.retain(|page| !done.contains(&page.fullname));
+
+        let mut results = stream::iter(
+            pages.into_iter()
+                .map(|page| async {
+                    let fullname = page.fullname.clone();
+                    (fullname, update_page(self.page_col.clone(), self.revision_col.clone(), page).await)
+                })
+        )
+        .buffered(self.concurrency);
+
+        let mut page_hash: HashMap<_, _> = HashMap::new();
+        let mut batch = Vec::new();
+        while let Some((fullname, result)) = results.next().await {
+            match result {
+                Ok(()) => batch.push(fullname),
+                Err(e) => {
+                    self.record_failure(JobKind::UpdatePage, &fullname, &e).await;
+                    page_hash.insert(fullname, e);
+                },
+            }
+            if batch.len() >= CHECKPOINT_BATCH {
+                self.checkpoint_pages(run, &mut batch).await?;
+            }
+        }
+        self.checkpoint_pages(run, &mut batch).await?;
+        run.counts.pages = (run.pages_done.len() + page_hash.len()) as i32;
+        run.counts.pages_failed = page_hash.len() as i32;
 
-        let pages = site.search(&[("category", "*")]).await?;
-        
+        // Pages that were never reached must not be mistaken for deleted ones
+        if run.full_sweep {
+            let done = run.pages_done.iter().collect::<HashSet<_>>();
+            if page_hash.is_empty() && listed.iter().all(|fullname| done.contains(fullname)) {
+                match mark_deleted_pages(self.page_col.clone(), &self.site.name, &run.page_ids, listed.len()).await? {
+                    Some(count) => info!(count, "marked deleted pages"),
+                    None => warn!(listed = listed.len(), "skipped deletion marking, listing looks truncated"),
+                }
+            }
+            else {
+                warn!("skipped deletion marking, sweep incomplete");
+            }
+        }
+        Ok(page_hash)
+    }
+
+    async fn checkpoint_users(&self, run: &mut MongoRun, batch: &mut Vec<i32>) -> Result<(), Box<dyn Error>>{
+        checkpoint_users(self.run_col.clone(), run, batch).await?;
+        let targets = batch.iter().map(|user_id| user_id.to_string()).collect::<Vec<_>>();
+        resolve_failures(self.failure_col.clone(), JobKind::UpdateUser, &targets).await?;
+        resolve_failures(self.failure_col.clone(), JobKind::AddUser, &targets).await?;
+        batch.clear();
+        Ok(())
+    }
+
+    // Queue bookkeeping must not abort the stage that produced the failure
+    async fn record_failure(&self, kind: JobKind, target: &str, error: &WikidotError){
+        warn!(?kind, target, error = %error, "job failed");
+        if let Err(e) = record_failure(self.failure_col.clone(), kind, target, error).await {
+            error!(?kind, target, error = %e, "failed recording failure");
+        }
+    }
+
+    async fn retry(&self, site: &Site, failure: &MongoFailure) -> Result<(), WikidotError>{
+        match failure.kind {
+            JobKind::UpdatePage => update_page(self.page_col.clone(), self.revision_col.clone(), find_page(site, &failure.target).await?).await,
+            JobKind::UpdateUser => update_user(self.client.clone(), self.user_col.clone(), failure.target.parse()?).await,
+            JobKind::AddUser => add_user(self.client.clone(), self.user_col.clone(), &self.site.name, failure.target.parse()?).await,
+        }
+    }
+
+    // Returns how many due jobs were retried and how many of them succeeded
+    async fn retry_failures(&self, site: &Site) -> Result<(usize, usize), Box<dyn Error>>{
+        let due = due_failures(self.failure_col.clone()).await?;
         let results = stream::iter(
-            pages.iter()
-                .map(|page| update_page(page_col.clone(), page.clone()))
+            due.iter()
+                .map(|failure| self.retry(site, failure))
         )
-        .buffered(semaphore)
+        .buffered(self.concurrency)
         .collect::<Vec<_>>()
         .await;
 
-        let mut page_hash: HashMap<_, _> = HashMap::new();
-        let mut user_hash: HashMap<_, _> = HashMap::new();
-
-        for (i, result) in results.into_iter().enumerate() {
-            if result.is_err() {
-                page_hash.insert(pages[i].fullname.clone(), result.err().unwrap());
+        let mut resolved = 0;
+        for (failure, result) in due.iter().zip(results){
+            match result {
+                Ok(()) => {
+                    resolve_failures(self.failure_col.clone(), failure.kind, std::slice::from_ref(&failure.target)).await?;
+                    resolved += 1;
+                },
+                Err(e) => self.record_failure(failure.kind, &failure.target, &e).await,
             }
         }
+        Ok((due.len(), resolved))
+    }
 
-        let _ = page_col.update_many(doc! { "id": { "$nin": PAGE_VEC.lock()?.clone() } }, doc! { "$set": {"status": false}}).await?;
-        
-        let response = client.get("https://backrooms-wiki-cn.wikidot.com/attribution-metadata").await?.text().await?;
+    async fn sync_authors(&self) -> Result<HashMap<String, WikidotError>, Box<dyn Error>>{
+        let response = self.client.get(&self.site.attribution_url).await?.text().await?;
         let html = Html::parse_document(&response);
-        let table = html.select(&selectors::TABLE).next().unwrap();
-        let _ = stream::iter(
+        let table = html.select(&selectors::TABLE).next().ok_or(ParseElementError::site_ele())?;
+        let results = stream::iter(
             table.select(&selectors::TR)
                 .skip(1)
-                .map(|tr| acquire_metadata(tr, site.clone(), page_col.clone()))
+                .map(|tr| acquire_metadata(tr, self.client.clone(), self.page_col.clone(), &self.ignored_users))
         )
-        .buffered(semaphore)
+        .buffered(self.concurrency)
         .collect::<Vec<_>>()
         .await;
-        
-        println!("{:?}, {:?}, {:?}", 
-            PAGE_VEC.lock()?, 
-            USER_NOW.lock()?, 
-            USER_ADD.lock()?
-        );
-
-        let update_users: Vec<i32> = user_col
+        let mut metadata_hash: HashMap<_, _> = HashMap::new();
+        collect_result!(metadata_hash, results, table.select(&selectors::TR).skip(1)
+            .map(|tr| tr.text().collect::<String>().trim().to_string()));
+        Ok(metadata_hash)
+    }
+
+    async fn crawl_users(&self, run: &mut MongoRun) -> Result<HashMap<i32, WikidotError>, Box<dyn Error>>{
+        let done = run.users_done.iter().copied().collect::<HashSet<_>>();
+        let update_users: Vec<i32> = self.user_col
             .distinct("id", doc! {"account_type": {"$ne": "deleted"}}).await?
             .into_iter()
             .map(|bson| bson.as_i32().unwrap())
+            .filter(|user_id| !done.contains(user_id))
             .collect();
+        let new_users = USER_ADD.lock()?.iter()
+            .copied()
+            .filter(|user_id| !done.contains(user_id))
+            .collect::<Vec<_>>();
 
-        let results = stream::iter(
-            update_users.iter()
-                .map(|&user_id| update_user(user_col.clone(), user_id))
+        let mut results = stream::iter(
+            update_users.iter().map(|&user_id| (user_id, false))
+                .chain(new_users.iter().map(|&user_id| (user_id, true)))
+                .map(|(user_id, new)| async move {
+                    let result = if new {add_user(self.client.clone(), self.user_col.clone(), &self.site.name, user_id).await} else {update_user(self.client.clone(), self.user_col.clone(), user_id).await};
+                    (user_id, new, result)
+                })
         )
-        .buffered(semaphore)
-        .collect::<Vec<_>>()
-        .await;
-        collect_result!(user_hash, results, update_users.iter().copied());
+        .buffered(self.concurrency);
 
+        let mut user_hash: HashMap<_, _> = HashMap::new();
+        let mut batch = Vec::new();
+        while let Some((user_id, new, result)) = results.next().await {
+            match result {
+                Ok(()) => batch.push(user_id),
+                Err(e) => {
+                    self.record_failure(if new {JobKind::AddUser} else {JobKind::UpdateUser}, &user_id.to_string(), &e).await;
+                    user_hash.insert(user_id, e);
+                },
+            }
+            if batch.len() >= CHECKPOINT_BATCH {
+                self.checkpoint_users(run, &mut batch).await?;
+            }
+        }
+        self.checkpoint_users(run, &mut batch).await?;
+        run.counts.users = (run.users_done.len() + user_hash.len()) as i32;
+        run.counts.users_failed = user_hash.len() as i32;
+        Ok(user_hash)
+    }
+
+    async fn add_users(&self) -> Result<HashMap<i32, WikidotError>, Box<dyn Error>>{
+        let mut user_hash: HashMap<_, _> = HashMap::new();
+        let new_users = USER_ADD.lock()?.to_vec();
         let results = stream::iter(
-            USER_ADD.lock()?.to_vec()
-                .iter()
-                .map(|user_id| add_user(user_col.clone(), *user_id))
+            new_users.iter()
+                .map(|user_id| add_user(self.client.clone(), self.user_col.clone(), &self.site.name, *user_id))
         )
-        .buffered(semaphore)
+        .buffered(self.concurrency)
         .collect::<Vec<_>>()
         .await;
-        collect_result!(user_hash, results, USER_ADD.lock()?.to_vec().iter().copied());
+        collect_result!(user_hash, results, new_users.iter().copied());
+        for (user_id, e) in &user_hash{
+            warn!(user_id, error = %e, "failed adding user");
+        }
+        Ok(user_hash)
+    }
+
+    async fn sync_alt_titles(&self) -> Result<(), Box<dyn Error>>{
+        for url in &self.site.alt_title_urls{
+            update_alt_titles(self.client.clone(), url, self.page_col.clone()).await?;
+        }
+        Ok(())
+    }
+
+    async fn sync_links(&self) -> Result<(), Box<dyn Error>>{
+        rebuild_links(self.page_col.clone(), self.link_col.clone()).await?;
+        let report = link_report(self.page_col.clone(), self.link_col.clone()).await?;
+        info!(orphans = report.orphans.len(), broken = report.broken.len(), deleted = report.deleted.len(), "link report");
+        debug!(orphans = ?report.orphans, broken = ?report.broken, deleted = ?report.deleted, "link report details");
+        Ok(())
+    }
+
+    async fn backfill_revisions(&self, site: &Site, budget: usize) -> Result<(), Box<dyn Error>>{
+        let (fetched, failed) = backfill_revision_sources(self.page_col.clone(), self.revision_col.clone(), site, budget, self.concurrency).await?;
+        info!(fetched, failed, "revision sources backfilled");
+        Ok(())
+    }
+
+    // Voters seen for the first time wait for the next run, fetching them here would break the flat request rate
+    async fn poll_round(&self, budget: usize) -> Result<(), Box<dyn Error>>{
+        let site = self.client.get_site(&self.site.name).await?;
+        let results = poll_votes(self.page_col.clone(), &site, budget, self.concurrency).await?;
+        let mut changed = 0;
+        let mut failed = 0;
+        let mut voters = HashSet::new();
+        for (candidate, result) in &results{
+            match result {
+                Ok((true, page_voters)) => {
+                    changed += 1;
+                    voters.extend(page_voters.iter().copied());
+                },
+                Ok((false, _)) => (),
+                Err(e) => {
+                    warn!(page = %candidate.fullname, error = %e, "vote poll failed");
+                    failed += 1;
+                },
+            }
+        }
+        let known = self.user_col.distinct("id", doc! {"id": {"$in": voters.iter().collect::<Vec<_>>()}}).await?
+            .into_iter()
+            .filter_map(|bson| bson.as_i32())
+            .collect::<HashSet<_>>();
+        let mut new_voters = self.new_voters.lock().unwrap();
+        new_voters.extend(voters.difference(&known));
+        info!(polled = results.len(), changed, failed, queued_users = new_voters.len(), "votes polled");
+        Ok(())
+    }
+
+    async fn crawl_page(&self, fullname: &str) -> Result<(), Box<dyn Error>>{
+        self.load_users(None).await?;
+        let site = self.client.get_site(&self.site.name).await?;
+        update_page(self.page_col.clone(), self.revision_col.clone(), find_page(&site, fullname).await?).await?;
+        self.add_users().await?;
+        Ok(())
+    }
+
+    async fn crawl_user(&self, id: i32) -> Result<(), Box<dyn Error>>{
+        if self.user_col.find_one(doc! {"id": id}).await?.is_some() {
+            update_user(self.client.clone(), self.user_col.clone(), id).await?;
+        }
+        else {
+            add_user(self.client.clone(), self.user_col.clone(), &self.site.name, id).await?;
+        }
+        Ok(())
+    }
+
+    // Saves progress and reports whether the run should go on to `stage`
+    async fn enter_stage(&self, run: &mut MongoRun, stage: &str, shutdown: &Shutdown) -> Result<bool, Box<dyn Error>>{
+        if *shutdown.borrow() {
+            run.status = RunStatus::Interrupted;
+            return Ok(false)
+        }
+        run.stage = stage.to_string();
+        save_run(self.run_col.clone(), run).await?;
+        Ok(true)
+    }
+
+    async fn run_once(&self, run: &mut MongoRun, shutdown: &Shutdown) -> Result<(), Box<dyn Error>>{
+        self.load_users(Some(run)).await?;
+        let site = self.client.get_site(&self.site.name).await?;
+
+        for stage in &STAGES[run.resume_stage()..]{
+            if !self.enter_stage(run, stage, shutdown).await? {return Ok(())}
+            self.run_stage(stage, &site, run)
+                .instrument(info_span!("stage", stage = *stage))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn run_stage(&self, stage: &str, site: &Site, run: &mut MongoRun) -> Result<(), Box<dyn Error>>{
+        match stage {
+            "pages" => {
+                let page_hash = self.crawl_pages(site, run).await?;
+                page_hash.iter().for_each(|(fullname, e)| run.fail("pages", fullname, e));
+                info!(pages = run.counts.pages, failed = page_hash.len(), "pages crawled");
+                debug!(pages = ?PAGE_VEC.lock()?, users = ?USER_NOW.lock()?, new_users = ?USER_ADD.lock()?, "collected ids");
+            },
+            "retries" => {
+                let (retried, resolved) = self.retry_failures(site).await?;
+                run.counts.retried = retried as i32;
+                run.counts.retries_resolved = resolved as i32;
+                info!(retried, resolved, "failed jobs retried");
+            },
+            "authors" => {
+                let metadata_hash = self.sync_authors().await?;
+                run.counts.metadata_failed = metadata_hash.len() as i32;
+                metadata_hash.iter().for_each(|(row, e)| run.fail("authors", row, e));
+                for (row, e) in &metadata_hash{
+                    warn!(row, error = %e, "metadata row failed");
+                }
+                info!(failed = metadata_hash.len(), "authors synced");
+            },
+            "users" => {
+                let user_hash = self.crawl_users(run).await?;
+                user_hash.iter().for_each(|(user_id, e)| run.fail("users", user_id, e));
+                info!(users = run.counts.users, failed = user_hash.len(), "users crawled");
+            },
+            "members" => if let Err(e) = update_members(self.member_col.clone(), site.clone()).await {
+                error!(error = %e, "failed syncing members");
+                run.fail("members", &self.site.name, e);
+            },
+            "alt_titles" => if let Err(e) = self.sync_alt_titles().await {
+                error!(error = %e, "failed syncing alt titles");
+                run.fail("alt_titles", &self.site.name, e);
+            },
+            "links" => if let Err(e) = self.sync_links().await {
+                error!(error = %e, "failed syncing links");
+                run.fail("links", &self.site.name, e);
+            },
+            "user_votes" => if let Err(e) = rebuild_user_votes(self.page_col.clone(), self.vote_col.clone()).await {
+                error!(error = %e, "failed rebuilding user votes");
+                run.fail("user_votes", &self.site.name, e);
+            },
+            "revisions" => if let Err(e) = self.backfill_revisions(site, self.revision_backfill_budget).await {
+                error!(error = %e, "failed backfilling revision sources");
+                run.fail("revisions", &self.site.name, e);
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
 
-        println!("failed pages: {:?}", page_hash);
-        println!("failed users: {:?}", user_hash);
+type Shutdown = watch::Receiver<bool>;
 
-        for url in ALT_TITLE_URLS{
-            update_alt_titles(client.clone(), url, page_col.clone()).await?;
+// The first SIGINT/SIGTERM lets the current stage finish, a second one exits right away
+fn listen_for_shutdown() -> Result<Shutdown, Box<dyn Error>>{
+    let (sender, receiver) = watch::channel(false);
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => (),
+                _ = terminate.recv() => (),
+            }
+            if *sender.borrow() {
+                warn!("forced shutdown");
+                std::process::exit(130);
+            }
+            info!("shutdown requested, stopping after the current stage");
+            let _ = sender.send(true);
         }
+    });
+    Ok(receiver)
+}
 
-        let end = DateTime::now();
-        println!("end: {}, duration: {}", end.timestamp_millis(), end.saturating_duration_since(start).as_secs());
+struct Schedule{
+    last_run: Option<DateTime>,
+    last_full: Option<DateTime>,
+    next_run: DateTime,
+    // None when vote polling is turned off
+    next_poll: Option<DateTime>,
+}
+
+// Sites run one at a time because the page and user id lists are process-wide
+async fn run_daemon(crawlers: Vec<Crawler>, interval: Option<u64>, full_interval: Option<u64>, mut shutdown: Shutdown) -> Result<(), Box<dyn Error>>{
+    let next_run = |crawler: &Crawler, after: DateTime| match interval {
+        Some(interval) => DateTime::from_millis(after.timestamp_millis() + interval as i64 * 1000),
+        None => crawler.site.next_run(after),
+    };
 
-        sleep(Duration::from_secs(21600));
+    // Picks up where an earlier daemon left off instead of starting with a full sweep,
+    // a site whose run history cannot be read starts with one right away
+    let mut schedules = Vec::new();
+    for crawler in &crawlers{
+        let (last_run, last_full) = match tokio::try_join!(
+            last_completed_run(crawler.run_col.clone(), &crawler.site.name, false),
+            last_completed_run(crawler.run_col.clone(), &crawler.site.name, true),
+        ) {
+            Ok((last_run, last_full)) => (last_run.map(|run| run.start), last_full.map(|run| run.start)),
+            Err(e) => {
+                error!(site = %crawler.site.name, error = %e, "failed loading last runs");
+                (None, None)
+            },
+        };
+        schedules.push(Schedule{
+            last_run,
+            last_full,
+            next_run: last_run.map(|start| next_run(crawler, start)).unwrap_or(DateTime::now()),
+            next_poll: (crawler.vote_poll_budget > 0).then(DateTime::now),
+        });
     }
+
+    while !*shutdown.borrow() {
+        for (crawler, schedule) in crawlers.iter().zip(schedules.iter_mut()){
+            // Rounds run between whole runs, so a long run delays them instead of racing its page updates
+            if let Some(next_poll) = schedule.next_poll.filter(|next_poll| !*shutdown.borrow() && DateTime::now() >= *next_poll) {
+                let span = info_span!("vote_poll", site = %crawler.site.name);
+                if let Err(e) = crawler.poll_round(crawler.vote_poll_budget).instrument(span.clone()).await {
+                    span.in_scope(|| error!(error = %e, "vote poll round failed"));
+                }
+                // Keeps the cadence unless a run held the round back by more than an interval
+                let interval = crawler.vote_poll_interval as i64 * 1000;
+                schedule.next_poll = Some(DateTime::from_millis(next_poll.timestamp_millis().max(DateTime::now().timestamp_millis() - interval) + interval));
+            }
+
+            let start = DateTime::now();
+            if *shutdown.borrow() || start < schedule.next_run {
+                continue
+            }
+            let full_interval = full_interval.unwrap_or(crawler.site.full_crawl_interval);
+            let full_sweep = match (schedule.last_run, schedule.last_full) {
+                (Some(_), Some(full)) => start.saturating_duration_since(full).as_secs() >= full_interval,
+                _ => true,
+            };
+
+            // Checkpoints older than a full sweep interval are stale, start over instead
+            let oldest = DateTime::from_millis(start.timestamp_millis() - full_interval as i64 * 1000);
+            let mut run = match unfinished_run(crawler.run_col.clone(), &crawler.site.name, oldest).await {
+                Ok(Some(mut run)) => {
+                    run.resume();
+                    info!(site = %crawler.site.name, run_id = %run.id, stage = %run.stage, "resuming run");
+                    run
+                },
+                Ok(None) => MongoRun::new(&crawler.site.name, if full_sweep {None} else {schedule.last_run}),
+                Err(e) => {
+                    error!(site = %crawler.site.name, error = %e, "failed loading checkpoint");
+                    MongoRun::new(&crawler.site.name, if full_sweep {None} else {schedule.last_run})
+                },
+            };
+            let span = info_span!("run", site = %crawler.site.name, run_id = %run.id, full_sweep = run.full_sweep);
+            span.in_scope(|| info!("run started"));
+
+            match crawler.run_once(&mut run, &shutdown).instrument(span.clone()).await {
+                Ok(()) if run.status == RunStatus::Interrupted => run.finish(RunStatus::Interrupted),
+                Ok(()) => run.finish(RunStatus::Completed),
+                Err(e) => {
+                    span.in_scope(|| error!(error = %e, "run failed"));
+                    run.error = Some(e.to_string());
+                    run.finish(RunStatus::Failed);
+                },
+            }
+            if let Err(e) = save_run(crawler.run_col.clone(), &run).await {
+                span.in_scope(|| error!(error = %e, "failed saving run"));
+            }
+
+            if run.status == RunStatus::Completed && run.counts.pages_failed == 0 {
+                schedule.last_run = Some(run.start);
+                if run.full_sweep {
+                    schedule.last_full = Some(run.start);
+                }
+            }
+
+            let duration = DateTime::now().saturating_duration_since(start).as_secs();
+            metrics::RUN_DURATION
+                .with_label_values(&[&crawler.site.name, if run.full_sweep {"full"} else {"incremental"}, &format!("{:?}", run.status).to_lowercase()])
+                .observe(duration as f64);
+            span.in_scope(|| info!(status = ?run.status, duration, "run finished"));
+            schedule.next_run = next_run(crawler, start);
+        }
+
+        let Some(next) = schedules.iter().flat_map(|schedule| [Some(schedule.next_run), schedule.next_poll]).flatten().min() else { break };
+        tokio::select! {
+            _ = tokio::time::sleep(next.saturating_duration_since(DateTime::now())) => (),
+            _ = shutdown.changed() => (),
+        }
+    }
+
+    info!("daemon stopped");
+    Ok(())
+}
+
+fn init_logging(verbose: u8, format: LogFormat){
+    let level = match verbose {
+        0 => "info",
+        1 => "debug",
+        _ => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("warn,wikidot={level}")));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>>{
+    let cli = Cli::parse();
+    init_logging(cli.verbose, cli.log_format);
+    let mut config = Config::load(&cli.config)?;
+    if let Some(concurrency) = cli.concurrency {
+        config.crawler.concurrency = concurrency;
+    }
+    config.validate()?;
+    let sites = config.select_sites(cli.site.as_deref(), cli.db.as_deref())?;
+
+    let mongo = mongodb::Client::with_uri_str(config.database.link.as_deref().unwrap_or_default())
+        .await?;
+    let mut client = AjaxClient::from(config.account.username.as_deref().unwrap_or_default(),
+        config.account.password.as_deref().unwrap_or_default()).await?
+        .with_request_limit(config.crawler.concurrency);
+    client.config = config.ajax.clone();
+    let mut crawlers = sites.into_iter()
+        .map(|site| {
+            let db = mongo.database(&site.database);
+            Crawler{
+                client: client.clone(),
+                site,
+                ignored_users: config.crawler.ignored_users.clone(),
+                concurrency: config.crawler.concurrency,
+                vote_poll_interval: config.crawler.vote_poll_interval,
+                vote_poll_budget: config.crawler.vote_poll_budget,
+                revision_backfill_budget: config.crawler.revision_backfill_budget,
+                page_col: db.collection("pages"),
+                user_col: db.collection("users"),
+                member_col: db.collection("members"),
+                link_col: db.collection("links"),
+                vote_col: db.collection("user_votes"),
+                revision_col: db.collection("revisions"),
+                new_voters: Mutex::new(HashSet::new()),
+                failure_col: db.collection("failures"),
+                run_col: db.collection("runs"),
+            }
+        })
+        .collect::<Vec<_>>();
+    for crawler in &crawlers{
+        create_revision_indexes(crawler.revision_col.clone()).await?;
+    }
+
+    if let Command::RunDaemon{interval, full_interval} = cli.command {
+        if let Some(addr) = &config.crawler.metrics_listen {
+            let listener = TcpListener::bind(addr).await?;
+            info!(addr = %addr, "serving metrics");
+            let sites = crawlers.iter().map(|crawler| crawler.site.name.clone()).collect::<Vec<_>>();
+            tokio::spawn(async move {
+                if let Err(e) = serve_metrics(listener, &sites).await {
+                    error!(error = %e, "metrics server stopped");
+                }
+            });
+        }
+        return run_daemon(crawlers, interval, full_interval, listen_for_shutdown()?).await
+    }
+    let crawler = crawlers.remove(0);
+    match cli.command {
+        Command::CrawlPages{since_hours} => {
+            crawler.load_users(None).await?;
+            let site = crawler.client.get_site(&crawler.site.name).await?;
+            let since = since_hours.map(|hours| DateTime::from_millis(DateTime::now().timestamp_millis() - hours as i64 * 3600 * 1000));
+            // Recorded like a daemon run, so a daemon started later resumes it if it stops halfway
+            let mut run = MongoRun::new(&crawler.site.name, since);
+            run.stage = String::from("pages");
+            save_run(crawler.run_col.clone(), &run).await?;
+            let page_hash = crawler.crawl_pages(&site, &mut run).await?;
+            info!(pages = run.counts.pages, failed = page_hash.len(), "pages crawled");
+            crawler.add_users().await?;
+            run.finish(RunStatus::Completed);
+            save_run(crawler.run_col.clone(), &run).await?;
+        },
+        Command::CrawlUsers => {
+            crawler.load_users(None).await?;
+            let user_hash = crawler.crawl_users(&mut MongoRun::new(&crawler.site.name, None)).await?;
+            info!(failed = user_hash.len(), "users crawled");
+        },
+        Command::SyncAuthors => {
+            let metadata_hash = crawler.sync_authors().await?;
+            for (row, e) in &metadata_hash{
+                warn!(row, error = %e, "metadata row failed");
+            }
+        },
+        Command::SyncAltTitles => crawler.sync_alt_titles().await?,
+        Command::SyncMembers => {
+            let site = crawler.client.get_site(&crawler.site.name).await?;
+            update_members(crawler.member_col.clone(), site).await?;
+        },
+        Command::SyncLinks => crawler.sync_links().await?,
+        Command::Backlinks{fullname} => {
+            let fullname = to_fullname(&fullname).ok_or(TargetNotExist::page())?;
+            for page in what_links_here(crawler.link_col.clone(), &fullname).await?{
+                println!("links: {page}");
+            }
+            for page in pages_including(crawler.link_col.clone(), &fullname).await?{
+                println!("includes: {page}");
+            }
+        },
+        Command::VoteReport{limit, json} => {
+            let findings = vote_report(crawler.page_col.clone(), crawler.user_col.clone()).await?;
+            let findings = &findings[..limit.min(findings.len())];
+            if json {
+                println!("{}", serde_json::to_string_pretty(findings)?);
+            }
+            else {
+                for finding in findings{
+                    println!("{:.1} {:?} users: {:?}, pages: {:?}", finding.score, finding.kind, finding.users, finding.pages);
+                    for line in &finding.evidence{
+                        println!("    {line}");
+                    }
+                }
+            }
+        },
+        Command::BackfillRevisions{budget} => {
+            let site = crawler.client.get_site(&crawler.site.name).await?;
+            crawler.backfill_revisions(&site, budget.unwrap_or(crawler.revision_backfill_budget)).await?;
+        },
+        Command::SyncUserVotes => rebuild_user_votes(crawler.page_col.clone(), crawler.vote_col.clone()).await?,
+        Command::UserVotes{id} => {
+            println!("{}", serde_json::to_string_pretty(&vote_summary(crawler.vote_col.clone(), id).await?)?);
+            for vote in user_votes(crawler.vote_col.clone(), id, 0, 0).await?{
+                let changes = vote.changes.iter()
+                    .map(|change| format!("{} {:+} -> {:+}", change.timestamp, change.from, change.to))
+                    .collect::<Vec<_>>();
+                println!("{} ({}): {:+}, {}", vote.fullname, vote.page_id, vote.value, changes.join(", "));
+            }
+        },
+        Command::PollVotes{budget} => crawler.poll_round(budget.unwrap_or(crawler.vote_poll_budget)).await?,
+        Command::RetryFailures => {
+            crawler.load_users(None).await?;
+            let site = crawler.client.get_site(&crawler.site.name).await?;
+            let (retried, resolved) = crawler.retry_failures(&site).await?;
+            info!(retried, resolved, "failed jobs retried");
+            crawler.add_users().await?;
+        },
+        Command::FailureReport => {
+            for failure in failure_report(crawler.failure_col.clone()).await?{
+                println!("{:?} {}: {} attempts, {} ({}), first failed {}, last failed {}",
+                    failure.kind, failure.target, failure.attempts, failure.error_kind, failure.error, failure.first_failed, failure.last_failed);
+            }
+        },
+        Command::Page{fullname} => crawler.crawl_page(&fullname).await?,
+        Command::User{id} => crawler.crawl_user(id).await?,
+        Command::RunDaemon{..} => (),
+    }
+
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,69 @@
+use axum::{http::header, routing::get, Router};
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec, TextEncoder};
+use tokio::net::TcpListener;
+
+pub static REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "wikidot_requests_total", "Requests sent to Wikidot, every attempt counted", &["module"]
+).unwrap());
+
+pub static REQUEST_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "wikidot_request_retries_total", "Failed request attempts that were retried", &["module"]
+).unwrap());
+
+pub static TRY_AGAIN: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "wikidot_try_again_total", "Responses with body status 'try_again'", &["module"]
+).unwrap());
+
+pub static REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| register_histogram_vec!(
+    "wikidot_request_duration_seconds", "Latency of a single request attempt", &["module"]
+).unwrap());
+
+pub static PARSE_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "wikidot_parse_errors_total", "Element parse errors by ParseElementError kind", &["kind"]
+).unwrap());
+
+pub static PAGES_UPDATED: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "wikidot_pages_updated_total", "Archived pages refreshed", &["site"]
+).unwrap());
+
+pub static PAGES_INSERTED: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "wikidot_pages_inserted_total", "Pages archived for the first time", &["site"]
+).unwrap());
+
+pub static PAGES_MARKED_DELETED: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "wikidot_pages_marked_deleted_total", "Pages marked deleted after a full sweep", &["site"]
+).unwrap());
+
+pub static USERS_ADDED: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "wikidot_users_added_total", "Users archived for the first time", &["site"]
+).unwrap());
+
+pub static VOTE_POLLS: Lazy<IntCounterVec> = Lazy::new(|| register_int_counter_vec!(
+    "wikidot_vote_polls_total", "Pages whose votes were polled between runs", &["tier"]
+).unwrap());
+
+pub static RUN_DURATION: Lazy<HistogramVec> = Lazy::new(|| register_histogram_vec!(
+    "wikidot_run_duration_seconds", "Duration of daemon runs", &["site", "mode", "status"],
+    vec![60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0, 14400.0, 28800.0, 86400.0]
+).unwrap());
+
+// Counters only show up once a label is used, so every site starts at zero before the first scrape
+fn register(sites: &[String]){
+    for site in sites{
+        for counter in [&PAGES_UPDATED, &PAGES_INSERTED, &PAGES_MARKED_DELETED, &USERS_ADDED]{
+            counter.with_label_values(&[site]);
+        }
+    }
+}
+
+pub fn render() -> String{
+    TextEncoder::new().encode_to_string(&prometheus::gather()).unwrap_or_default()
+}
+
+pub async fn serve_metrics(listener: TcpListener, sites: &[String]) -> std::io::Result<()>{
+    register(sites);
+    let app = Router::new()
+        .route("/metrics", get(|| async { ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], render()) }));
+    axum::serve(listener, app).await
+}
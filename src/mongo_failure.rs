@@ -0,0 +1,93 @@
+use futures::TryStreamExt;
+use mongodb::bson::{doc, DateTime};
+use serde::{Deserialize, Serialize};
+use crate::error::WikidotError;
+
+// Retries stop after this many attempts and the job shows up in the failure report
+pub const MAX_ATTEMPTS: i32 = 8;
+const BASE_BACKOFF: i64 = 600;
+const MAX_BACKOFF: i64 = 86400;
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind{
+    UpdatePage,
+    UpdateUser,
+    AddUser,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct MongoFailure{
+    pub kind: JobKind,
+    // Page fullname or user id
+    pub target: String,
+    pub error_kind: String,
+    pub error: String,
+    pub attempts: i32,
+    pub first_failed: DateTime,
+    pub last_failed: DateTime,
+    pub next_retry: DateTime,
+    pub permanent: bool,
+}
+
+impl JobKind{
+    fn as_str(&self) -> &'static str{
+        match self {
+            JobKind::UpdatePage => "update_page",
+            JobKind::UpdateUser => "update_user",
+            JobKind::AddUser => "add_user",
+        }
+    }
+}
+
+fn backoff(attempts: i32) -> i64{
+    (BASE_BACKOFF << (attempts - 1).clamp(0, 16)).min(MAX_BACKOFF)
+}
+
+pub async fn record_failure(collection: mongodb::Collection<MongoFailure>, kind: JobKind, target: &str, error: &WikidotError) -> Result<(), WikidotError>{
+    let now = DateTime::now();
+    let filter = doc! {"kind": kind.as_str(), "target": target};
+    let previous = collection.find_one(filter.clone()).await?;
+    let attempts = previous.as_ref().map_or(0, |failure| failure.attempts) + 1;
+    // A missing page or user will not come back by retrying
+    let gone = matches!(error, WikidotError::TargetNotExist(_));
+
+    collection.replace_one(filter, MongoFailure{
+        kind,
+        target: target.to_string(),
+        error_kind: error.kind(),
+        error: error.to_string(),
+        attempts,
+        first_failed: previous.map_or(now, |failure| failure.first_failed),
+        last_failed: now,
+        next_retry: DateTime::from_millis(now.timestamp_millis() + backoff(attempts) * 1000),
+        permanent: gone || attempts >= MAX_ATTEMPTS,
+    })
+    .upsert(true)
+    .await?;
+    Ok(())
+}
+
+pub async fn resolve_failures(collection: mongodb::Collection<MongoFailure>, kind: JobKind, targets: &[String]) -> Result<(), WikidotError>{
+    if targets.is_empty() {
+        return Ok(())
+    }
+    collection.delete_many(doc! {"kind": kind.as_str(), "target": {"$in": targets}}).await?;
+    Ok(())
+}
+
+pub async fn due_failures(collection: mongodb::Collection<MongoFailure>) -> Result<Vec<MongoFailure>, WikidotError>{
+    Ok(collection.find(doc! {"permanent": false, "next_retry": {"$lte": DateTime::now()}})
+        .sort(doc! {"next_retry": 1})
+        .await?
+        .try_collect()
+        .await?)
+}
+
+pub async fn failure_report(collection: mongodb::Collection<MongoFailure>) -> Result<Vec<MongoFailure>, WikidotError>{
+    Ok(collection.find(doc! {"permanent": true})
+        .sort(doc! {"kind": 1, "last_failed": -1})
+        .await?
+        .try_collect()
+        .await?)
+}
@@ -0,0 +1,189 @@
+use std::collections::{HashMap, HashSet};
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use crate::{error::WikidotError, mongo_page::MongoPage, page_links::{to_fullname, PageLinks}};
+
+#[derive(Deserialize, Serialize)]
+pub struct MongoLinks{
+    pub id: i32,
+    pub fullname: String,
+    // Latest revision the links were read from, 0 forces a rebuild
+    #[serde(default)]
+    pub revision: i32,
+    pub links: Vec<String>,
+    pub urls: Vec<String>,
+    pub includes: Vec<MongoInclude>,
+    pub images: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MongoInclude{
+    pub target: String,
+    pub variables: HashMap<String, String>,
+}
+
+#[derive(Default, Serialize, Debug)]
+pub struct LinkReport{
+    pub orphans: Vec<String>,
+    pub broken: Vec<(String, String)>,
+    pub deleted: Vec<(String, String)>,
+}
+
+#[derive(Deserialize)]
+struct MongoPageStatus{
+    fullname: String,
+    status: bool,
+}
+
+#[derive(Deserialize)]
+struct MongoPageSource{
+    id: i32,
+    source: String,
+}
+
+// Sources are large, so changed pages are read a batch at a time
+const SOURCE_BATCH: usize = 200;
+
+#[derive(Deserialize)]
+struct MongoPageRevisions{
+    id: i32,
+    fullname: String,
+    #[serde(default)]
+    history: Vec<RevisionId>,
+}
+
+#[derive(Deserialize)]
+struct RevisionId{
+    index: i16,
+    id: i32,
+}
+
+#[derive(Deserialize)]
+struct MongoLinksRevision{
+    id: i32,
+    fullname: String,
+    #[serde(default)]
+    revision: i32,
+}
+
+pub async fn update_links(collection: mongodb::Collection<MongoLinks>, id: i32, fullname: String, revision: i32, source: &str) -> Result<(), WikidotError>{
+    let links = PageLinks::from_source(source);
+    collection.replace_one(doc! {"id": id}, MongoLinks{
+        id,
+        fullname,
+        revision,
+        links: links.pages,
+        urls: links.urls,
+        includes: links.includes.into_iter()
+            .map(|include| MongoInclude{target: include.target, variables: include.variables.into_iter().collect()})
+            .collect(),
+        images: links.images,
+    })
+    .upsert(true)
+    .await?;
+
+    Ok(())
+}
+
+// Only re-reads the sources of pages with a new revision since their links were stored; renames are revisions too
+pub async fn rebuild_links(page_col: mongodb::Collection<MongoPage>, collection: mongodb::Collection<MongoLinks>) -> Result<(), WikidotError>{
+    let stored = collection.clone_with_type::<MongoLinksRevision>()
+        .find(doc! {})
+        .projection(doc! {"id": 1, "fullname": 1, "revision": 1})
+        .await?
+        .map_ok(|links| (links.id, (links.fullname, links.revision)))
+        .try_collect::<HashMap<_, _>>().await?;
+
+    let mut ids = Vec::new();
+    let mut changed = Vec::new();
+    let mut cursor = page_col.clone_with_type::<MongoPageRevisions>()
+        .find(doc! {"status": true})
+        .projection(doc! {"id": 1, "fullname": 1, "history.index": 1, "history.id": 1})
+        .await?;
+    while let Some(page) = cursor.try_next().await? {
+        ids.push(page.id);
+        let revision = page.history.iter().max_by_key(|revision| revision.index).map_or(0, |revision| revision.id);
+        let up_to_date = stored.get(&page.id).is_some_and(|(fullname, stored)| *fullname == page.fullname && *stored == revision && revision != 0);
+        if !up_to_date {
+            changed.push((page.id, page.fullname, revision));
+        }
+    }
+
+    let source_col = page_col.clone_with_type::<MongoPageSource>();
+    for batch in changed.chunks(SOURCE_BATCH){
+        let mut pages = batch.iter()
+            .map(|(id, fullname, revision)| (*id, (fullname, *revision)))
+            .collect::<HashMap<_, _>>();
+        let mut cursor = source_col
+            .find(doc! {"id": {"$in": pages.keys().copied().collect::<Vec<_>>()}, "status": true})
+            .projection(doc! {"id": 1, "source": 1})
+            .await?;
+        while let Some(page) = cursor.try_next().await? {
+            let Some((fullname, revision)) = pages.remove(&page.id) else { continue };
+            update_links(collection.clone(), page.id, fullname.clone(), revision, &page.source).await?;
+        }
+    }
+
+    collection.delete_many(doc! {"id": {"$nin": ids}}).await?;
+    Ok(())
+}
+
+pub async fn what_links_here(collection: mongodb::Collection<MongoLinks>, fullname: &str) -> Result<Vec<String>, WikidotError>{
+    Ok(collection.distinct("fullname", doc! {"links": fullname}).await?
+        .into_iter()
+        .filter_map(|bson| bson.as_str().map(|s| s.to_string()))
+        .collect())
+}
+
+pub async fn pages_including(collection: mongodb::Collection<MongoLinks>, target: &str) -> Result<Vec<String>, WikidotError>{
+    Ok(collection.distinct("fullname", doc! {"includes.target": target.trim().to_lowercase()}).await?
+        .into_iter()
+        .filter_map(|bson| bson.as_str().map(|s| s.to_string()))
+        .collect())
+}
+
+// Orphans have no links or includes from other live pages; broken links point at pages never archived,
+// deleted links point at pages whose archived copies are all marked `status: false`
+pub async fn link_report(page_col: mongodb::Collection<MongoPage>, collection: mongodb::Collection<MongoLinks>) -> Result<LinkReport, WikidotError>{
+    let mut live = HashSet::new();
+    let mut known = HashSet::new();
+    let mut cursor = page_col.clone_with_type::<MongoPageStatus>()
+        .find(doc! {})
+        .projection(doc! {"fullname": 1, "status": 1})
+        .await?;
+    while let Some(page) = cursor.try_next().await? {
+        if page.status {
+            live.insert(page.fullname.clone());
+        }
+        known.insert(page.fullname);
+    }
+
+    let mut report = LinkReport::default();
+    let mut linked = HashSet::new();
+    let mut cursor = collection.find(doc! {}).await?;
+    while let Some(links) = cursor.try_next().await? {
+        if !live.contains(&links.fullname) {
+            continue;
+        }
+        let includes = links.includes.iter()
+            .filter(|include| !include.target.starts_with(':'))
+            .filter_map(|include| to_fullname(&include.target));
+        for target in links.links.iter().cloned().chain(includes){
+            if target == links.fullname {
+                continue;
+            }
+            if !known.contains(&target) {
+                report.broken.push((links.fullname.clone(), target.clone()));
+            }
+            else if !live.contains(&target) {
+                report.deleted.push((links.fullname.clone(), target.clone()));
+            }
+            linked.insert(target);
+        }
+    }
+
+    report.orphans = live.difference(&linked).cloned().collect();
+    report.orphans.sort();
+    Ok(report)
+}
@@ -0,0 +1,36 @@
+use mongodb::bson::{doc, DateTime};
+use serde::{Deserialize, Serialize};
+use crate::{error::WikidotError, site::Site, site_member::MemberRole};
+
+#[derive(Deserialize, Serialize)]
+pub struct MongoMember{
+    pub id: i32,
+    role: MemberRole,
+    join: Option<DateTime>,
+    status: bool,
+}
+
+pub async fn update_members(collection: mongodb::Collection<MongoMember>, site: Site) -> Result<(), WikidotError>{
+    let members = site.members().await?;
+    let mut member_ids = Vec::new();
+
+    for member in members{
+        let Some(user_id) = member.user.id else { continue };
+        member_ids.push(user_id);
+        collection.replace_one(doc! {"id": user_id}, MongoMember{
+            id: user_id,
+            role: member.role,
+            join: member.joined_at,
+            status: true,
+        })
+        .upsert(true)
+        .await?;
+    }
+
+    if member_ids.is_empty() {
+        return Ok(())
+    }
+    collection.update_many(doc! {"id": {"$nin": member_ids}}, doc! {"$set": {"status": false}}).await?;
+
+    Ok(())
+}
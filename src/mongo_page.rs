@@ -1,41 +1,209 @@
-use std::collections::HashMap;
-use futures::{stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+use futures::{stream, StreamExt, TryStreamExt};
 use mongodb::bson::{doc, DateTime};
 use scraper::{ElementRef, Html};
 use serde::{Deserialize, Serialize};
-use crate::{client::AjaxClient, error::{ParseElementError, WikidotError}, page::Page, page_history::Revision, selectors};
+use tracing::debug;
+use crate::{client::AjaxClient, error::{ParseElementError, WikidotError}, metrics, mongo_revision::{save_revision_source, MongoRevisionSource}, page::Page, page_history::Revision, page_text::PageText, selectors};
 
 #[derive(Deserialize, Serialize)]
 pub struct MongoRateHistory{
-    timestamp: DateTime,
-    votes: HashMap<String, i8>,
-    up: i16,
-    down: i16,
+    pub timestamp: DateTime,
+    pub votes: HashMap<String, i8>,
+    pub up: i16,
+    pub down: i16,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct MongoRevision{
-    index: i16,
-    id: i32,
-    types: Vec<char>,
-    created_by: i32,
-    created_at: Option<DateTime>,
-    comment: String,
+    pub index: i16,
+    pub id: i32,
+    pub types: Vec<char>,
+    // None for anonymous and guest edits
+    pub created_by: Option<i32>,
+    pub created_at: Option<DateTime>,
+    pub comment: String,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct MongoPage{
-    id: i32,
-    author: Vec<i32>,
+    pub id: i32,
+    pub author: Vec<i32>,
+    pub fullname: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    // Left out of projections that only need metadata
+    #[serde(default)]
+    pub source: String,
+    pub rate_history: Vec<MongoRateHistory>,
+    // Last time the votes were fetched, rate_history only grows when they change
+    #[serde(default)]
+    pub votes_polled: Option<DateTime>,
+    // Copies of the listing dates and the newest rate_history timestamp, so polls need neither array
+    #[serde(default)]
+    pub created_at: Option<DateTime>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime>,
+    #[serde(default)]
+    pub last_change: Option<DateTime>,
+    #[serde(default)]
+    pub history: Vec<MongoRevision>,
+    pub comments_count: i16,
+    pub status: bool,
+    pub alternative: String,
+    // Missing on pages archived before text stats existed
+    #[serde(default)]
+    pub text_stats: Option<MongoTextStats>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct MongoTextStats{
+    pub words: i32,
+    pub characters: i32,
+    pub cjk_characters: i32,
+    pub reading_time: i32,
+}
+
+impl MongoTextStats{
+    fn from(source: &str) -> Self{
+        let text = PageText::from_source(source);
+        MongoTextStats{
+            words: text.words,
+            characters: text.characters,
+            cjk_characters: text.cjk_characters,
+            reading_time: text.reading_time,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RatingPoint{
+    pub timestamp: DateTime,
+    pub up: i16,
+    pub down: i16,
+    pub rating: i16,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct VoteChange{
+    pub timestamp: DateTime,
+    pub user_id: i32,
+    pub from: i8,
+    pub to: i8,
+}
+
+fn apply_diff(votes: &mut HashMap<String, i8>, diff: &HashMap<String, i8>){
+    for (user_id, vote) in diff{
+        match vote {
+            0 => votes.remove(user_id),
+            _ => votes.insert(user_id.clone(), *vote),
+        };
+    }
+}
+
+impl MongoPage{
+    // Only the newest entry keeps the full vote map. When votes change, the previous entry is cut down
+    // to the earlier values of the votes that changed, 0 standing for no vote. Returns whether it did
+    pub fn record_votes(&mut self, votes: HashMap<String, i8>, up: i16, down: i16, timestamp: DateTime) -> bool{
+        if let Some(last) = self.rate_history.last_mut() {
+            let mut diff = last.votes.iter()
+                .filter(|(user_id, vote)| votes.get(*user_id) != Some(vote))
+                .map(|(user_id, vote)| (user_id.clone(), *vote))
+                .collect::<HashMap<_, _>>();
+            diff.extend(votes.keys()
+                .filter(|user_id| !last.votes.contains_key(*user_id))
+                .map(|user_id| (user_id.clone(), 0)));
+            if diff.is_empty() {
+                return false
+            }
+            last.votes = diff;
+        }
+        self.rate_history.push(MongoRateHistory{timestamp, votes, up, down});
+        self.last_change = Some(timestamp);
+        true
+    }
+
+    // Votes as of the last poll at or before `timestamp`, empty before the page was first archived
+    pub fn votes_at(&self, timestamp: DateTime) -> HashMap<i32, i8>{
+        let Some(latest) = self.rate_history.last() else { return HashMap::new() };
+        let mut votes = latest.votes.clone();
+        let mut found = latest.timestamp <= timestamp;
+        for entry in self.rate_history.iter().rev().skip(1){
+            if found {
+                break
+            }
+            apply_diff(&mut votes, &entry.votes);
+            found = entry.timestamp <= timestamp;
+        }
+        if !found {
+            return HashMap::new()
+        }
+        votes.into_iter()
+            .filter_map(|(user_id, vote)| Some((user_id.parse().ok()?, vote)))
+            .collect()
+    }
+
+    // One point per poll that changed votes, oldest first, counted from the replayed votes
+    pub fn rating_timeline(&self) -> Vec<RatingPoint>{
+        let Some(latest) = self.rate_history.last() else { return Vec::new() };
+        let mut votes = latest.votes.clone();
+        let mut timeline = Vec::with_capacity(self.rate_history.len());
+        for (i, entry) in self.rate_history.iter().enumerate().rev(){
+            if i + 1 < self.rate_history.len() {
+                apply_diff(&mut votes, &entry.votes);
+            }
+            timeline.push(RatingPoint{
+                timestamp: entry.timestamp,
+                up: votes.values().filter(|vote| **vote > 0).count() as i16,
+                down: votes.values().filter(|vote| **vote < 0).count() as i16,
+                rating: votes.values().map(|vote| *vote as i16).sum(),
+            });
+        }
+        timeline.reverse();
+        timeline
+    }
+
+    // Votes cast, changed or removed by poll, oldest first; votes already there at the first poll come from 0
+    pub fn vote_changes(&self) -> Vec<VoteChange>{
+        let Some(first) = self.rate_history.first() else { return Vec::new() };
+        let mut votes = self.rate_history.last().map(|latest| latest.votes.clone()).unwrap_or_default();
+        let mut changes = Vec::new();
+        for (i, entry) in self.rate_history.iter().enumerate().rev().skip(1){
+            let timestamp = self.rate_history[i + 1].timestamp;
+            for (user_id, from) in &entry.votes{
+                let to = votes.get(user_id).copied().unwrap_or(0);
+                if let Ok(user_id) = user_id.parse() {
+                    changes.push(VoteChange{timestamp, user_id, from: *from, to});
+                }
+            }
+            apply_diff(&mut votes, &entry.votes);
+        }
+        changes.extend(votes.iter()
+            .filter_map(|(user_id, vote)| Some(VoteChange{timestamp: first.timestamp, user_id: user_id.parse().ok()?, from: 0, to: *vote})));
+        changes.sort_by_key(|change| (change.timestamp, change.user_id));
+        changes
+    }
+}
+
+#[derive(Deserialize)]
+struct MongoPageDigest{
     fullname: String,
-    title: String,
-    tags: Vec<String>,
-    source: String,
-    rate_history: Vec<MongoRateHistory>,
-    history: Vec<MongoRevision>,
     comments_count: i16,
-    status: bool,
-    alternative: String,
+    rate_history: Vec<MongoRateHistory>,
+}
+
+fn rate_counts(page: &Page) -> (i16, i16){
+    let up: i16 = (page.votes_count + page.rating as i16) / 2;
+    let down: i16 = (page.votes_count - page.rating as i16) / 2;
+    (up, down)
+}
+
+// The current source is the source of the newest revision
+async fn archive_source(collection: mongodb::Collection<MongoRevisionSource>, page_id: i32, history: &[MongoRevision], source: &str) -> Result<(), WikidotError>{
+    if let Some(latest) = history.iter().max_by_key(|revision| revision.index) {
+        save_revision_source(collection, page_id, latest.id, source).await?;
+    }
+    Ok(())
 }
 
 fn process_revisions(revisions: Vec<Revision>) -> Vec<MongoRevision>{
@@ -45,7 +213,7 @@ fn process_revisions(revisions: Vec<Revision>) -> Vec<MongoRevision>{
             index: revision.index,
             id: revision.id,
             types: revision.types,
-            created_by: revision.created_by.id.unwrap(),
+            created_by: revision.created_by.id,
             created_at: revision.created_at,
             comment: revision.comment,
         });
@@ -53,12 +221,11 @@ fn process_revisions(revisions: Vec<Revision>) -> Vec<MongoRevision>{
     rev
 }
 
-pub async fn update_page(collection: mongodb::Collection<MongoPage>, mut page: Page) -> Result<(), WikidotError>{
-    println!("{}", page.fullname);
+#[tracing::instrument(name = "page", skip_all, fields(page = %page.fullname))]
+pub async fn update_page(collection: mongodb::Collection<MongoPage>, revision_col: mongodb::Collection<MongoRevisionSource>, mut page: Page) -> Result<(), WikidotError>{
     let mut new_rates: HashMap<String, i8> = HashMap::new();
     let mongo_page;
-    let up: i16 = (page.votes_count + page.rating as i16) / 2;
-    let down: i16 = (page.votes_count - page.rating as i16) / 2;
+    let (up, down) = rate_counts(&page);
     let filter = if let Some(user_id) = page.created_by.id {
         doc! {
             "$expr": {"$eq": [{"$arrayElemAt": ["$history.created_at", -1]}, page.created_at]},
@@ -73,75 +240,117 @@ pub async fn update_page(collection: mongodb::Collection<MongoPage>, mut page: P
         if page.updated_at != old_page.rate_history[0].timestamp{
             old_page.source = page.acquire_page_source().await?;
             old_page.history = process_revisions(page.acquire_revisions(&["all"]).await?);
+            archive_source(revision_col, old_page.id, &old_page.history, &old_page.source).await?;
+            old_page.text_stats = Some(MongoTextStats::from(&old_page.source));
+        }
+        // Pages archived before text stats existed get them on their next update
+        else if old_page.text_stats.is_none() {
+            old_page.text_stats = Some(MongoTextStats::from(&old_page.source));
         }
 
-        let mut old_rates = old_page.rate_history;
-        let last = old_rates.pop().ok_or(ParseElementError::mongo_ele())?;
-        let mut last_votes = last.votes.clone();
-
-        let mut diff: HashMap<String, i8> = HashMap::new();
+        if old_page.rate_history.is_empty() {
+            Err(ParseElementError::mongo_ele())?
+        }
         for vote in page.acquire_votes().await?{
-            let user_id = vote.user.id.unwrap();
-            if let Some(matched_vote) = last.votes.get(&user_id.to_string()){
-                if matched_vote != &vote.rate{
-                    diff.insert(user_id.to_string(), *matched_vote);
-                }
-            }
-            else{
-                diff.insert(user_id.to_string(), 0);
+            if let Some(user_id) = vote.user.id {
+                new_rates.insert(user_id.to_string(), vote.rate);
             }
-            last_votes.remove(&user_id.to_string());
-            new_rates.insert(user_id.to_string(), vote.rate);
         }
+        let polled = DateTime::now();
+        let votes_changed = old_page.record_votes(new_rates, up, down, polled);
+        // Pages archived before last_change existed
+        let last_change = old_page.last_change.or(old_page.rate_history.last().map(|entry| entry.timestamp));
 
-        diff.extend(last_votes);
-
-        if diff.is_empty(){
-            old_rates.push(last);
-        }
-        else {
-            old_rates.push(MongoRateHistory{votes: diff, ..last});
-            old_rates.push(MongoRateHistory{timestamp: DateTime::now(), votes: new_rates, up, down});
-        }
-        
         mongo_page = MongoPage{
+            votes_polled: Some(polled),
+            created_at: Some(page.created_at),
+            updated_at: Some(page.updated_at),
+            last_change,
             fullname: page.fullname,
-            title: page.title.unwrap_or(String::new()),
+            title: page.title.unwrap_or_default(),
             tags: page.tags,
-            rate_history: old_rates,
             comments_count: page.comments_count,
             status: true,
             ..old_page
         };
         collection.replace_one(doc! {"id": old_page.id}, &mongo_page).await?;
+        metrics::PAGES_UPDATED.with_label_values(&[&page.site.unix_name]).inc();
+        debug!(id = mongo_page.id, votes_changed, "updated page");
     }
     else {
         page.acquire_id().await?;
         for vote in page.acquire_votes().await?{
-            new_rates.insert(vote.user.id.unwrap().to_string(), vote.rate);
+            if let Some(user_id) = vote.user.id {
+                new_rates.insert(user_id.to_string(), vote.rate);
+            }
         }
+        let polled = DateTime::now();
         let revisions = page.acquire_revisions(&["all"]).await?;
         let source = page.acquire_page_source().await?;
+        let author = page.created_by.id.or(revisions.last().and_then(|revision| revision.created_by.id));
+        let history = process_revisions(revisions);
+        archive_source(revision_col, page.id.unwrap(), &history, &source).await?;
 
         mongo_page = MongoPage{
             id: page.id.unwrap(),
-            author: vec![page.created_by.id.unwrap_or(revisions.last().unwrap().created_by.id.unwrap())],
+            author: author.into_iter().collect(),
             fullname: page.fullname,
-            title: page.title.unwrap_or(String::new()),
+            title: page.title.unwrap_or_default(),
+            text_stats: Some(MongoTextStats::from(&source)),
             source,
             tags: page.tags,
-            rate_history: vec![MongoRateHistory{timestamp: DateTime::now(), votes: new_rates, up, down}],
-            history: process_revisions(revisions),
+            rate_history: vec![MongoRateHistory{timestamp: polled, votes: new_rates, up, down}],
+            votes_polled: Some(polled),
+            created_at: Some(page.created_at),
+            updated_at: Some(page.updated_at),
+            last_change: Some(polled),
+            history,
             comments_count: page.comments_count,
             status: true,
             alternative: String::new(),
         };
         collection.insert_one(&mongo_page).await?;
+        metrics::PAGES_INSERTED.with_label_values(&[&page.site.unix_name]).inc();
+        debug!(id = mongo_page.id, "inserted page");
     }
 
     Ok(())
 }
 
+pub async fn filter_stale_pages(collection: mongodb::Collection<MongoPage>, pages: Vec<Page>, changed: &HashSet<String>) -> Result<Vec<Page>, WikidotError>{
+    let mut stored: HashMap<String, (i16, i16, i16)> = HashMap::new();
+    let mut cursor = collection.clone_with_type::<MongoPageDigest>()
+        .find(doc! {"status": true})
+        .projection(doc! {"fullname": 1, "comments_count": 1, "rate_history": {"$slice": -1}})
+        .await?;
+    while let Some(digest) = cursor.try_next().await? {
+        let last = digest.rate_history.last().ok_or(ParseElementError::mongo_ele())?;
+        stored.insert(digest.fullname, (last.up, last.down, digest.comments_count));
+    }
+
+    Ok(pages.into_iter()
+        .filter(|page| {
+            let (up, down) = rate_counts(page);
+            changed.contains(&page.fullname)
+                || stored.get(&page.fullname) != Some(&(up, down, page.comments_count))
+        })
+        .collect())
+}
+
+// A full listing shorter than this share of live pages is treated as truncated
+const MIN_LISTING_RATIO: f64 = 0.9;
+
+// Marks live pages missing from a verified full sweep as deleted, returns None when the listing looks truncated
+pub async fn mark_deleted_pages(collection: mongodb::Collection<MongoPage>, site: &str, live_ids: &[i32], listed: usize) -> Result<Option<u64>, WikidotError>{
+    let stored = collection.count_documents(doc! {"status": true}).await?;
+    if (listed as f64) < stored as f64 * MIN_LISTING_RATIO {
+        return Ok(None)
+    }
+    let result = collection.update_many(doc! {"status": true, "id": {"$nin": live_ids}}, doc! {"$set": {"status": false}}).await?;
+    metrics::PAGES_MARKED_DELETED.with_label_values(&[site]).inc_by(result.modified_count);
+    Ok(Some(result.modified_count))
+}
+
 pub async fn update_alt_titles(client: AjaxClient, url: &str, db: mongodb::Collection<MongoPage>) -> Result<(), WikidotError>{
     let text = client.get(url).await?.text().await?;
     let html = Html::parse_fragment(&text);
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use futures::{stream, StreamExt, TryStreamExt};
+use mongodb::bson::{doc, DateTime};
+use serde::Deserialize;
+use tracing::{debug, warn};
+use crate::{error::{TargetNotExist, WikidotError}, metrics, mongo_page::MongoPage, site::Site};
+
+const MINUTE: i64 = 60 * 1000;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+// A crawl can rewrite the rate history between reading and writing it, the write is then redone this often
+const WRITE_ATTEMPTS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PollTier{
+    Hot,
+    Warm,
+    Cold,
+}
+
+impl PollTier{
+    // New pages and pages voted on lately are hot, old pages nobody touched for a while cold
+    pub fn of(created: Option<DateTime>, updated: Option<DateTime>, last_change: Option<DateTime>, now: DateTime) -> Self{
+        let age = |date: Option<DateTime>| date.map_or(i64::MAX, |date| now.timestamp_millis() - date.timestamp_millis());
+        if age(created) < 3 * DAY || age(last_change) < 12 * HOUR {
+            PollTier::Hot
+        }
+        else if age(created) < 30 * DAY || age(updated) < 7 * DAY || age(last_change) < 3 * DAY {
+            PollTier::Warm
+        }
+        else {
+            PollTier::Cold
+        }
+    }
+
+    // How often a page of this tier should be polled, in milliseconds
+    pub fn interval(self) -> i64{
+        match self {
+            PollTier::Hot => 20 * MINUTE,
+            PollTier::Warm => 2 * HOUR,
+            PollTier::Cold => DAY,
+        }
+    }
+
+    pub fn label(self) -> &'static str{
+        match self {
+            PollTier::Hot => "hot",
+            PollTier::Warm => "warm",
+            PollTier::Cold => "cold",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PollCandidate{
+    pub page_id: i32,
+    pub fullname: String,
+    pub tier: PollTier,
+    pub polled: DateTime,
+}
+
+impl PollCandidate{
+    // Above 1 once the page has waited longer than its tier allows
+    pub fn overdue(&self, now: DateTime) -> f64{
+        (now.timestamp_millis() - self.polled.timestamp_millis()) as f64 / self.tier.interval() as f64
+    }
+}
+
+// Always spends the whole budget, on the most overdue pages first, so the request rate stays flat
+pub fn select_due(mut candidates: Vec<PollCandidate>, now: DateTime, budget: usize) -> Vec<PollCandidate>{
+    candidates.sort_by(|a, b| b.overdue(now).total_cmp(&a.overdue(now))
+        .then(a.tier.cmp(&b.tier))
+        .then(a.page_id.cmp(&b.page_id)));
+    candidates.truncate(budget);
+    candidates
+}
+
+// Pages get these fields on their next update, until then they are left to the crawl
+#[derive(Deserialize)]
+struct PollDigest{
+    id: i32,
+    fullname: String,
+    #[serde(default)]
+    votes_polled: Option<DateTime>,
+    #[serde(default)]
+    created_at: Option<DateTime>,
+    #[serde(default)]
+    updated_at: Option<DateTime>,
+    #[serde(default)]
+    last_change: Option<DateTime>,
+}
+
+pub async fn poll_candidates(collection: mongodb::Collection<MongoPage>, now: DateTime) -> Result<Vec<PollCandidate>, WikidotError>{
+    let mut cursor = collection.clone_with_type::<PollDigest>()
+        .find(doc! {"status": true})
+        .projection(doc! {"id": 1, "fullname": 1, "votes_polled": 1, "created_at": 1, "updated_at": 1, "last_change": 1})
+        .await?;
+    let mut candidates = Vec::new();
+    while let Some(digest) = cursor.try_next().await? {
+        // Pages archived before polls were recorded were at least polled at their last change
+        let Some(polled) = digest.votes_polled.or(digest.last_change) else { continue };
+        candidates.push(PollCandidate{
+            page_id: digest.id,
+            fullname: digest.fullname,
+            tier: PollTier::of(digest.created_at, digest.updated_at, digest.last_change, now),
+            polled,
+        });
+    }
+    Ok(candidates)
+}
+
+// Returns whether the votes changed since the last poll, and who voted
+#[tracing::instrument(name = "poll", skip_all, fields(page = %candidate.fullname, tier = candidate.tier.label()))]
+async fn poll_page(collection: mongodb::Collection<MongoPage>, site: &Site, candidate: &PollCandidate) -> Result<(bool, Vec<i32>), WikidotError>{
+    let votes = site.page_votes(candidate.page_id).await?
+        .into_iter()
+        .filter_map(|vote| Some((vote.user.id?.to_string(), vote.rate)))
+        .collect::<HashMap<_, _>>();
+    let voters = votes.keys().filter_map(|user_id| user_id.parse().ok()).collect();
+    let polled = DateTime::now();
+    let up = votes.values().filter(|vote| **vote > 0).count() as i16;
+    let down = votes.values().filter(|vote| **vote < 0).count() as i16;
+
+    for _ in 0..WRITE_ATTEMPTS{
+        let mut page = collection.find_one(doc! {"id": candidate.page_id})
+            .projection(doc! {"source": 0, "history": 0})
+            .await?
+            .ok_or(TargetNotExist::page())?;
+        let entries = page.rate_history.len() as i32;
+        let changed = page.record_votes(votes.clone(), up, down, polled);
+        let mut update = doc! {"votes_polled": polled};
+        if changed {
+            update.insert("rate_history", mongodb::bson::to_bson(&page.rate_history).map_err(mongodb::error::Error::from)?);
+            update.insert("last_change", polled);
+        }
+        // Only written over the history it was recorded against
+        let result = collection.update_one(doc! {"id": candidate.page_id, "rate_history": {"$size": entries}}, doc! {"$set": update}).await?;
+        if result.matched_count == 0 {
+            debug!("rate history changed while polling, retrying");
+            continue;
+        }
+        metrics::VOTE_POLLS.with_label_values(&[candidate.tier.label()]).inc();
+        debug!(changed, "polled votes");
+        return Ok((changed, voters))
+    }
+    warn!("rate history kept changing while polling, left for the next poll");
+    Ok((false, voters))
+}
+
+// One round of `budget` WhoRatedPageModule requests, results in the order the pages were picked
+pub async fn poll_votes(collection: mongodb::Collection<MongoPage>, site: &Site, budget: usize, concurrency: usize) -> Result<Vec<(PollCandidate, Result<(bool, Vec<i32>), WikidotError>)>, WikidotError>{
+    let now = DateTime::now();
+    let due = select_due(poll_candidates(collection.clone(), now).await?, now, budget);
+    let results = stream::iter(
+        due.iter()
+            .map(|candidate| poll_page(collection.clone(), site, candidate))
+    )
+    .buffered(concurrency)
+    .collect::<Vec<_>>()
+    .await;
+    Ok(due.into_iter().zip(results).collect())
+}
@@ -0,0 +1,101 @@
+use std::{cmp::Reverse, collections::HashSet};
+use futures::{stream, StreamExt, TryStreamExt};
+use mongodb::{bson::doc, options::IndexOptions, IndexModel};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use crate::{error::WikidotError, mongo_page::MongoPage, site::Site};
+
+// Kept apart from the page, a long history of full sources would outgrow the document size limit
+#[derive(Deserialize, Serialize)]
+pub struct MongoRevisionSource{
+    pub page_id: i32,
+    pub revision_id: i32,
+    pub source: String,
+}
+
+#[derive(Deserialize)]
+struct MongoPageRevisions{
+    id: i32,
+    #[serde(default)]
+    history: Vec<RevisionId>,
+}
+
+#[derive(Deserialize)]
+struct RevisionId{
+    id: i32,
+}
+
+#[derive(Deserialize)]
+struct MongoRevisionKey{
+    page_id: i32,
+    revision_id: i32,
+}
+
+pub async fn create_revision_indexes(collection: mongodb::Collection<MongoRevisionSource>) -> Result<(), WikidotError>{
+    collection.create_index(IndexModel::builder()
+        .keys(doc! {"page_id": 1, "revision_id": 1})
+        .options(IndexOptions::builder().unique(true).build())
+        .build()
+    ).await?;
+    Ok(())
+}
+
+pub async fn save_revision_source(collection: mongodb::Collection<MongoRevisionSource>, page_id: i32, revision_id: i32, source: &str) -> Result<(), WikidotError>{
+    collection.update_one(
+        doc! {"page_id": page_id, "revision_id": revision_id},
+        doc! {"$set": {"source": source}},
+    ).upsert(true).await?;
+    Ok(())
+}
+
+pub async fn revision_source(collection: mongodb::Collection<MongoRevisionSource>, page_id: i32, revision_id: i32) -> Result<Option<String>, WikidotError>{
+    Ok(collection.find_one(doc! {"page_id": page_id, "revision_id": revision_id}).await?
+        .map(|revision| revision.source))
+}
+
+async fn fetch_revision_source(collection: mongodb::Collection<MongoRevisionSource>, site: &Site, page_id: i32, revision_id: i32) -> Result<(), WikidotError>{
+    let source = site.revision_source(revision_id).await?;
+    save_revision_source(collection, page_id, revision_id, &source).await
+}
+
+// Fetches up to `budget` sources of live revisions that were never archived, newest first,
+// returns how many were fetched and how many of them failed
+pub async fn backfill_revision_sources(page_col: mongodb::Collection<MongoPage>, collection: mongodb::Collection<MongoRevisionSource>, site: &Site, budget: usize, concurrency: usize) -> Result<(usize, usize), WikidotError>{
+    let archived = collection.clone_with_type::<MongoRevisionKey>()
+        .find(doc! {})
+        .projection(doc! {"page_id": 1, "revision_id": 1})
+        .await?
+        .map_ok(|key| (key.page_id, key.revision_id))
+        .try_collect::<HashSet<_>>().await?;
+
+    let mut missing = Vec::new();
+    let mut cursor = page_col.clone_with_type::<MongoPageRevisions>()
+        .find(doc! {"status": true})
+        .projection(doc! {"id": 1, "history.id": 1})
+        .await?;
+    while let Some(page) = cursor.try_next().await? {
+        missing.extend(page.history.iter()
+            .map(|revision| (page.id, revision.id))
+            .filter(|key| !archived.contains(key)));
+    }
+    // Revision ids grow with time
+    missing.sort_by_key(|&(_, revision_id)| Reverse(revision_id));
+    missing.truncate(budget);
+
+    let results = stream::iter(
+        missing.iter()
+            .map(|&(page_id, revision_id)| fetch_revision_source(collection.clone(), site, page_id, revision_id))
+    )
+    .buffered(concurrency)
+    .collect::<Vec<_>>()
+    .await;
+
+    let mut failed = 0;
+    for ((page_id, revision_id), result) in missing.iter().zip(results){
+        if let Err(e) = result {
+            warn!(page_id, revision_id, error = %e, "failed fetching revision source");
+            failed += 1;
+        }
+    }
+    Ok((missing.len(), failed))
+}
@@ -0,0 +1,149 @@
+use std::{collections::HashSet, fmt::Display};
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+use crate::error::WikidotError;
+
+pub const STAGES: [&str; 9] = ["pages", "retries", "authors", "users", "members", "alt_titles", "links", "user_votes", "revisions"];
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus{
+    Running,
+    Completed,
+    Failed,
+    Interrupted,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct MongoRunCounts{
+    pub pages: i32,
+    pub pages_failed: i32,
+    pub users: i32,
+    pub users_failed: i32,
+    pub metadata_failed: i32,
+    pub retried: i32,
+    pub retries_resolved: i32,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct MongoRunFailure{
+    pub stage: String,
+    pub item: String,
+    pub error: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct MongoRun{
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub site: String,
+    pub full_sweep: bool,
+    pub since: Option<DateTime>,
+    pub start: DateTime,
+    pub end: Option<DateTime>,
+    pub status: RunStatus,
+    pub stage: String,
+    pub counts: MongoRunCounts,
+    pub failures: Vec<MongoRunFailure>,
+    pub error: Option<String>,
+    // Checkpoint, so an unfinished run can resume where it stopped
+    #[serde(default)]
+    pub pages_done: Vec<String>,
+    #[serde(default)]
+    pub page_ids: Vec<i32>,
+    #[serde(default)]
+    pub users_found: Vec<i32>,
+    #[serde(default)]
+    pub users_done: Vec<i32>,
+    #[serde(default)]
+    pub resumed: i32,
+}
+
+impl MongoRun{
+    pub fn new(site: &str, since: Option<DateTime>) -> Self{
+        MongoRun{
+            id: ObjectId::new(),
+            site: site.to_string(),
+            full_sweep: since.is_none(),
+            since,
+            start: DateTime::now(),
+            end: None,
+            status: RunStatus::Running,
+            stage: String::new(),
+            counts: MongoRunCounts::default(),
+            failures: Vec::new(),
+            error: None,
+            pages_done: Vec::new(),
+            page_ids: Vec::new(),
+            users_found: Vec::new(),
+            users_done: Vec::new(),
+            resumed: 0,
+        }
+    }
+
+    // Drops failures of the stage being retried, earlier stages stay done
+    pub fn resume(&mut self){
+        let stage = self.resume_stage();
+        self.failures.retain(|failure| STAGES.iter().position(|s| *s == failure.stage).is_some_and(|i| i < stage));
+        self.resumed += 1;
+        self.status = RunStatus::Running;
+        self.end = None;
+        self.error = None;
+    }
+
+    // Index into STAGES of the stage to start from
+    pub fn resume_stage(&self) -> usize{
+        STAGES.iter().position(|stage| *stage == self.stage).unwrap_or(0)
+    }
+
+    pub fn fail<T: Display, E: Display>(&mut self, stage: &str, item: T, error: E){
+        self.failures.push(MongoRunFailure{stage: stage.to_string(), item: item.to_string(), error: error.to_string()});
+    }
+
+    pub fn finish(&mut self, status: RunStatus){
+        self.status = status;
+        self.end = Some(DateTime::now());
+    }
+}
+
+pub async fn save_run(collection: mongodb::Collection<MongoRun>, run: &MongoRun) -> Result<(), WikidotError>{
+    collection.replace_one(doc! {"_id": run.id}, run).upsert(true).await?;
+    Ok(())
+}
+
+// Latest run that completed with every page refreshed
+pub async fn last_completed_run(collection: mongodb::Collection<MongoRun>, site: &str, full_sweep: bool) -> Result<Option<MongoRun>, WikidotError>{
+    let mut filter = doc! {"site": site, "status": "completed", "counts.pages_failed": 0};
+    if full_sweep {
+        filter.insert("full_sweep", true);
+    }
+    Ok(collection.find_one(filter).sort(doc! {"start": -1}).await?)
+}
+
+// Latest run of the site if it never finished and started after `after`
+pub async fn unfinished_run(collection: mongodb::Collection<MongoRun>, site: &str, after: DateTime) -> Result<Option<MongoRun>, WikidotError>{
+    let run = collection.find_one(doc! {"site": site}).sort(doc! {"start": -1}).await?;
+    Ok(run.filter(|run| run.status != RunStatus::Completed && run.start > after))
+}
+
+// Both keep the in-memory run in step, since save_run replaces the whole document
+pub async fn checkpoint_pages(collection: mongodb::Collection<MongoRun>, run: &mut MongoRun, fullnames: &[String], page_ids: &[i32], users_found: &[i32]) -> Result<(), WikidotError>{
+    run.pages_done.extend_from_slice(fullnames);
+    let known = run.page_ids.iter().copied().collect::<HashSet<_>>();
+    run.page_ids.extend(page_ids.iter().filter(|id| !known.contains(id)));
+    let known = run.users_found.iter().copied().collect::<HashSet<_>>();
+    run.users_found.extend(users_found.iter().filter(|id| !known.contains(id)));
+    collection.update_one(doc! {"_id": run.id}, doc! {"$addToSet": {
+        "pages_done": {"$each": fullnames},
+        "page_ids": {"$each": page_ids},
+        "users_found": {"$each": users_found},
+    }}).await?;
+    Ok(())
+}
+
+pub async fn checkpoint_users(collection: mongodb::Collection<MongoRun>, run: &mut MongoRun, user_ids: &[i32]) -> Result<(), WikidotError>{
+    run.users_done.extend_from_slice(user_ids);
+    collection.update_one(doc! {"_id": run.id}, doc! {"$addToSet": {"users_done": {"$each": user_ids}}}).await?;
+    Ok(())
+}
@@ -2,8 +2,9 @@ use mongodb::bson::{doc, DateTime};
 use serde::{Deserialize, Serialize};
 use once_cell::sync::Lazy;
 use std::sync::Mutex;
+use tracing::debug;
 
-use crate::{client::AjaxClient, error::{ParseElementError, WikidotError}};
+use crate::{client::AjaxClient, error::{ParseElementError, WikidotError}, metrics, user::UserProperty};
 
 pub static USER_ADD: Lazy<Mutex<Vec<i32>>> = Lazy::new(|| Mutex::new(Vec::new()));
 pub static USER_NOW: Lazy<Mutex<Vec<i32>>> = Lazy::new(|| Mutex::new(Vec::new()));
@@ -11,32 +12,73 @@ pub static USER_NOW: Lazy<Mutex<Vec<i32>>> = Lazy::new(|| Mutex::new(Vec::new())
 #[derive(Deserialize, Serialize)]
 pub struct MongoUser{
     pub id: i32,
-    join: DateTime,
-    title: Vec<String>,
-    avatar: Vec<MongoAvatar>,
-    karma: Vec<MongoKarma>,
-    account_type: String,
+    pub join: DateTime,
+    pub title: Vec<String>,
+    pub avatar: Vec<MongoAvatar>,
+    pub karma: Vec<MongoKarma>,
+    pub account_type: String,
+    #[serde(default)]
+    pub profile: Vec<MongoProfile>,
 }
 
 #[derive(Deserialize, Serialize)]
-struct MongoKarma{
-    level: i8,
-    timestamp: DateTime,
+pub struct MongoKarma{
+    pub level: i8,
+    pub timestamp: DateTime,
 }
 
 #[derive(Deserialize, Serialize)]
-struct MongoAvatar{
-    image: String,
-    timestamp: DateTime,
+pub struct MongoAvatar{
+    pub image: String,
+    pub timestamp: DateTime,
 }
 
-pub async fn update_user(collection: mongodb::Collection<MongoUser>, user_id: i32) -> Result<(), WikidotError>{
-    let user = AjaxClient::new().user(user_id).await?;
-    println!("{:?}", user.title);
+#[derive(Deserialize, Serialize)]
+pub struct MongoProfile{
+    pub real_name: Option<String>,
+    pub gender: Option<String>,
+    pub birthday: Option<String>,
+    pub location: Option<String>,
+    pub website: Option<String>,
+    pub about: Option<String>,
+    pub pro: Option<bool>,
+    pub timestamp: DateTime,
+}
+
+impl MongoProfile{
+    fn from(user: &UserProperty) -> Self{
+        MongoProfile{
+            real_name: user.real_name.clone(),
+            gender: user.gender.clone(),
+            birthday: user.birthday.clone(),
+            location: user.location.clone(),
+            website: user.website.clone(),
+            about: user.about.clone(),
+            pro: user.pro,
+            timestamp: DateTime::now(),
+        }
+    }
+
+    fn changed(&self, other: &MongoProfile) -> bool{
+        self.real_name != other.real_name
+            || self.gender != other.gender
+            || self.birthday != other.birthday
+            || self.location != other.location
+            || self.website != other.website
+            || self.about != other.about
+            || self.pro != other.pro
+    }
+}
+
+#[tracing::instrument(name = "user", skip(client, collection))]
+pub async fn update_user(client: AjaxClient, collection: mongodb::Collection<MongoUser>, user_id: i32) -> Result<(), WikidotError>{
+    let user = client.user(user_id).await?;
+    debug!(title = %user.title, "fetched user");
     let mut user_history = match collection.find_one(doc! {"id": user_id}).await? {
         Some(history) => history,
         None => return Ok(()),
     };
+    let profile = MongoProfile::from(&user);
     if user_history.title.last().ok_or(ParseElementError::mongo_ele())? != &user.title && !user.title.is_empty(){
         user_history.title.push(user.title);
     }
@@ -49,6 +91,10 @@ pub async fn update_user(collection: mongodb::Collection<MongoUser>, user_id: i3
         user_history.karma.push(MongoKarma{level: user.karma, timestamp: DateTime::now()});
     }
 
+    if user_history.profile.last().is_none_or(|last| last.changed(&profile)){
+        user_history.profile.push(profile);
+    }
+
     let _ = collection.replace_one(doc! {"id": user_id}, MongoUser{
         id: user_id,
         join: user.since, 
@@ -59,17 +105,21 @@ pub async fn update_user(collection: mongodb::Collection<MongoUser>, user_id: i3
     Ok(())
 }
 
-pub async fn add_user(collection: mongodb::Collection<MongoUser>, user_id: i32) -> Result<(), WikidotError>{
-    let user = AjaxClient::new().user(user_id).await?;
-    println!("{:?}", user.title);
+#[tracing::instrument(name = "user", skip(client, collection))]
+pub async fn add_user(client: AjaxClient, collection: mongodb::Collection<MongoUser>, site: &str, user_id: i32) -> Result<(), WikidotError>{
+    let user = client.user(user_id).await?;
+    debug!(title = %user.title, "fetched user");
+    let profile = MongoProfile::from(&user);
     let _ = collection.insert_one(MongoUser{
         id: user_id,
         join: user.since, 
         account_type: user.account_type, 
+        profile: vec![profile],
         title: vec![user.title],
         avatar: vec![MongoAvatar{image: user.avatar, timestamp: DateTime::now()}],
         karma: vec![MongoKarma{level: user.karma, timestamp: DateTime::now()}],
     }).await?;
+    metrics::USERS_ADDED.with_label_values(&[site]).inc();
 
     Ok(())
 }
\ No newline at end of file
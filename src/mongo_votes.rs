@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, HashMap};
+use futures::TryStreamExt;
+use mongodb::{bson::{doc, DateTime}, IndexModel};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use crate::{error::WikidotError, mongo_page::MongoPage};
+
+// One user's vote on one page, rebuilt from the page's rate history
+#[derive(Deserialize, Serialize)]
+pub struct MongoUserVote{
+    pub user_id: i32,
+    pub page_id: i32,
+    pub fullname: String,
+    pub page_status: bool,
+    // 0 once the vote is removed
+    pub value: i8,
+    pub first_seen: DateTime,
+    pub last_changed: DateTime,
+    pub removed: Option<DateTime>,
+    pub changes: Vec<MongoVoteChange>,
+    // Length of the rate history this was rebuilt from, pages that did not grow are skipped
+    #[serde(default)]
+    pub rate_entries: i32,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MongoVoteChange{
+    pub timestamp: DateTime,
+    pub from: i8,
+    pub to: i8,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct MonthVotes{
+    pub up: i32,
+    pub down: i32,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct VoteSummary{
+    pub user_id: i32,
+    pub pages: i32,
+    pub up: i32,
+    pub down: i32,
+    pub removed: i32,
+    pub changed: i32,
+    // Share of current votes that are upvotes, None without any
+    pub upvote_ratio: Option<f64>,
+    // Votes cast or changed to a new value, keyed by "YYYY-MM"
+    pub per_month: BTreeMap<String, MonthVotes>,
+}
+
+fn month(date: DateTime) -> String{
+    chrono::DateTime::from_timestamp_millis(date.timestamp_millis())
+        .unwrap_or_default()
+        .format("%Y-%m")
+        .to_string()
+}
+
+fn page_votes(page: &MongoPage) -> Vec<MongoUserVote>{
+    let mut votes: HashMap<i32, MongoUserVote> = HashMap::new();
+    for change in page.vote_changes(){
+        let vote = votes.entry(change.user_id).or_insert_with(|| MongoUserVote{
+            user_id: change.user_id,
+            page_id: page.id,
+            fullname: page.fullname.clone(),
+            page_status: page.status,
+            value: 0,
+            first_seen: change.timestamp,
+            last_changed: change.timestamp,
+            removed: None,
+            changes: Vec::new(),
+            rate_entries: page.rate_history.len() as i32,
+        });
+        vote.value = change.to;
+        vote.last_changed = change.timestamp;
+        vote.removed = (change.to == 0).then_some(change.timestamp);
+        vote.changes.push(MongoVoteChange{timestamp: change.timestamp, from: change.from, to: change.to});
+    }
+    votes.into_values().collect()
+}
+
+#[derive(Deserialize)]
+struct VotedPage{
+    #[serde(rename = "_id")]
+    id: i32,
+    fullname: String,
+    #[serde(rename = "page_status")]
+    status: bool,
+    #[serde(default)]
+    rate_entries: i32,
+}
+
+#[derive(Deserialize)]
+struct MongoPageDigest{
+    id: i32,
+    fullname: String,
+    status: bool,
+    rate_entries: i32,
+    latest_votes: i32,
+}
+
+// Pages are read in full to replay their votes, so stale ones are fetched a batch at a time
+const PAGE_BATCH: usize = 200;
+
+pub async fn create_vote_indexes(collection: mongodb::Collection<MongoUserVote>) -> Result<(), WikidotError>{
+    collection.create_indexes([
+        IndexModel::builder().keys(doc! {"user_id": 1, "first_seen": 1}).build(),
+        IndexModel::builder().keys(doc! {"page_id": 1}).build(),
+    ]).await?;
+    Ok(())
+}
+
+// Rate history only grows when votes change, so pages with as many entries as last time are skipped,
+// and so are pages that never had a vote, which have nothing stored to compare against
+pub async fn rebuild_user_votes(page_col: mongodb::Collection<MongoPage>, collection: mongodb::Collection<MongoUserVote>) -> Result<(), WikidotError>{
+    create_vote_indexes(collection.clone()).await?;
+    let mut rebuilt = HashMap::new();
+    let mut cursor = collection.aggregate([doc! {"$group": {
+        "_id": "$page_id",
+        "fullname": {"$first": "$fullname"},
+        "page_status": {"$first": "$page_status"},
+        "rate_entries": {"$first": "$rate_entries"},
+    }}]).with_type::<VotedPage>().await?;
+    while let Some(page) = cursor.try_next().await? {
+        rebuilt.insert(page.id, page);
+    }
+
+    let mut stale = Vec::new();
+    let mut cursor = page_col.clone_with_type::<MongoPageDigest>()
+        .find(doc! {})
+        .projection(doc! {
+            "id": 1,
+            "fullname": 1,
+            "status": 1,
+            "rate_entries": {"$size": "$rate_history"},
+            "latest_votes": {"$size": {"$objectToArray": {"$ifNull": [{"$arrayElemAt": ["$rate_history.votes", -1]}, {}]}}},
+        })
+        .await?;
+    while let Some(page) = cursor.try_next().await? {
+        let unchanged = match rebuilt.get(&page.id) {
+            Some(votes) => votes.rate_entries == page.rate_entries && votes.fullname == page.fullname && votes.status == page.status,
+            None => page.rate_entries <= 1 && page.latest_votes == 0,
+        };
+        if !unchanged {
+            stale.push(page.id);
+        }
+    }
+    debug!(stale = stale.len(), "rebuilding user votes");
+
+    for batch in stale.chunks(PAGE_BATCH){
+        let pages = page_col.find(doc! {"id": {"$in": batch}})
+            .projection(doc! {"source": 0, "history": 0})
+            .await?
+            .try_collect::<Vec<_>>().await?;
+        let votes = pages.iter().flat_map(page_votes).collect::<Vec<_>>();
+        collection.delete_many(doc! {"page_id": {"$in": batch}}).await?;
+        if !votes.is_empty() {
+            collection.insert_many(votes).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn user_votes(collection: mongodb::Collection<MongoUserVote>, user_id: i32, skip: u64, limit: i64) -> Result<Vec<MongoUserVote>, WikidotError>{
+    Ok(collection.find(doc! {"user_id": user_id})
+        .sort(doc! {"first_seen": 1, "page_id": 1})
+        .skip(skip)
+        .limit(limit)
+        .await?
+        .try_collect().await?)
+}
+
+pub async fn vote_summary(collection: mongodb::Collection<MongoUserVote>, user_id: i32) -> Result<VoteSummary, WikidotError>{
+    let mut summary = VoteSummary{user_id, ..VoteSummary::default()};
+    let mut cursor = collection.find(doc! {"user_id": user_id}).await?;
+    while let Some(vote) = cursor.try_next().await? {
+        summary.pages += 1;
+        match vote.value {
+            0 => summary.removed += 1,
+            value if value > 0 => summary.up += 1,
+            _ => summary.down += 1,
+        }
+        if vote.changes.len() > 1 {
+            summary.changed += 1;
+        }
+        for change in vote.changes.iter().filter(|change| change.to != 0){
+            let month = summary.per_month.entry(month(change.timestamp)).or_default();
+            if change.to > 0 {
+                month.up += 1;
+            }
+            else {
+                month.down += 1;
+            }
+        }
+    }
+    if summary.up + summary.down > 0 {
+        summary.upvote_ratio = Some(summary.up as f64 / (summary.up + summary.down) as f64);
+    }
+    Ok(summary)
+}
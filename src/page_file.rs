@@ -0,0 +1,210 @@
+use std::sync::{Arc, Mutex};
+use futures::stream;
+use regex::Regex;
+use reqwest::{multipart::{Form, Part}, Body};
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use crate::{client::AjaxClient, error::{FileActionError, ParseElementError, WikidotError}, page::Page, selectors};
+
+pub const UPLOAD_SIZE_LIMIT: usize = 10 * 1024 * 1024;
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+// Overwrites go up under this prefix first, so a failed upload leaves the original alone
+const UPLOAD_TEMP_PREFIX: &str = "upload-tmp-";
+// The original waits under this prefix until the new file has taken its name
+const UPLOAD_OLD_PREFIX: &str = "upload-old-";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PageFile{
+    pub id: i32,
+    pub name: String,
+    pub url: String,
+}
+
+impl Page {
+    pub async fn acquire_files(&mut self) -> Result<Vec<PageFile>, WikidotError>{
+        let page_id = self.acquire_id().await?;
+
+        let response = self.site.request(&[
+            ("page_id", &page_id.to_string()),
+            ("moduleName", "files/PageFilesModule"),
+        ]).await?;
+
+        parse_files(&response.body)
+    }
+
+    pub async fn upload_file(&mut self, name: &str, bytes: Vec<u8>, comment: &str) -> Result<(), WikidotError>{
+        self.upload_file_with_progress(name, bytes, comment, true, |_, _| {}).await
+    }
+
+    // progress is called with (bytes sent, total bytes) as each chunk is handed to the connection
+    pub async fn upload_file_with_progress<F>(
+        &mut self,
+        name: &str,
+        bytes: Vec<u8>,
+        comment: &str,
+        overwrite: bool,
+        progress: F,
+    ) -> Result<(), WikidotError>
+    where
+        F: FnMut(usize, usize) + Send + 'static,
+    {
+        if bytes.is_empty() {
+            Err(FileActionError::empty())?
+        }
+        if bytes.len() > UPLOAD_SIZE_LIMIT {
+            Err(FileActionError::too_large())?
+        }
+
+        let page_id = self.acquire_id().await?;
+
+        let existing = self.acquire_files().await?.into_iter().find(|file| file.name == name);
+        if existing.is_some() && !overwrite {
+            Err(FileActionError::exists())?
+        }
+        let Some(existing) = existing else {
+            return self.send_file(page_id, name, bytes, comment, progress).await
+        };
+
+        let temp_name = format!("{UPLOAD_TEMP_PREFIX}{name}");
+        self.send_file(page_id, &temp_name, bytes, comment, progress).await?;
+        let uploaded = self.acquire_files().await?
+            .into_iter()
+            .find(|file| file.name == temp_name)
+            .ok_or(FileActionError::not_found())?;
+
+        if let Err(e) = self.rename_file_id(existing.id, &format!("{UPLOAD_OLD_PREFIX}{name}")).await {
+            self.discard_file(uploaded.id).await;
+            return Err(e)
+        }
+        if let Err(e) = self.rename_file_id(uploaded.id, name).await {
+            if let Err(restore) = self.rename_file_id(existing.id, name).await {
+                warn!(file = name, error = %restore, "failed restoring the original file");
+            }
+            self.discard_file(uploaded.id).await;
+            return Err(e)
+        }
+        // The new file is in place, a leftover copy of the old one is not worth failing the upload for
+        self.discard_file(existing.id).await;
+        Ok(())
+    }
+
+    async fn discard_file(&self, file_id: i32){
+        if let Err(e) = self.delete_file_id(file_id).await {
+            warn!(file_id, error = %e, "failed deleting leftover file");
+        }
+    }
+
+    async fn send_file<F>(&self, page_id: i32, name: &str, bytes: Vec<u8>, comment: &str, progress: F) -> Result<(), WikidotError>
+    where
+        F: FnMut(usize, usize) + Send + 'static,
+    {
+        let total = bytes.len();
+        // Shared by every attempt, a retried upload reports its progress from the start again
+        let progress = Arc::new(Mutex::new(progress));
+        let form = || {
+            let progress = progress.clone();
+            let mut sent = 0;
+            let chunks = bytes.chunks(UPLOAD_CHUNK_SIZE).map(|chunk| chunk.to_vec()).collect::<Vec<_>>();
+            let body = Body::wrap_stream(stream::iter(chunks.into_iter().map(move |chunk| {
+                sent += chunk.len();
+                (progress.lock().unwrap())(sent, total);
+                Ok::<_, std::io::Error>(chunk)
+            })));
+            Form::new()
+                .text("action", "FileAction")
+                .text("event", "uploadFile")
+                .text("page_id", page_id.to_string())
+                .text("MAX_FILE_SIZE", UPLOAD_SIZE_LIMIT.to_string())
+                .text("dfilename", name.to_string())
+                .text("comments", comment.to_string())
+                .text("wikidot_token7", "123456")
+                .part("userfile", Part::stream_with_length(body, total as u64).file_name(name.to_string()))
+        };
+
+        let url = format!("{}/default--flow/files__UploadTarget", self.site.url());
+        let text = self.site.client.send_with_retry("uploadFile", &url,
+            |client| client.post(&url).multipart(form()),
+            |response| async { Ok(AjaxClient::process_response(response)?.text().await?) },
+        ).await?;
+        upload_status(&text)
+    }
+
+    pub async fn delete_file(&mut self, name: &str) -> Result<(), WikidotError>{
+        let file = self.acquire_files().await?
+            .into_iter()
+            .find(|file| file.name == name)
+            .ok_or(FileActionError::not_found())?;
+
+        self.delete_file_id(file.id).await
+    }
+
+    async fn delete_file_id(&self, file_id: i32) -> Result<(), WikidotError>{
+        let response = self.site.action(&[
+            ("action", "FileAction"),
+            ("event", "deleteFile"),
+            ("file_id", &file_id.to_string()),
+        ]).await?;
+
+        if response.status != "ok" {
+            Err(FileActionError::new("Delete", &response.message.unwrap_or(response.status)))?
+        }
+
+        Ok(())
+    }
+
+    async fn rename_file_id(&self, file_id: i32, new_name: &str) -> Result<(), WikidotError>{
+        let response = self.site.action(&[
+            ("action", "FileAction"),
+            ("event", "renameFile"),
+            ("file_id", &file_id.to_string()),
+            ("new_name", new_name),
+        ]).await?;
+
+        if response.status != "ok" {
+            Err(FileActionError::new("Rename", &response.message.unwrap_or(response.status)))?
+        }
+
+        Ok(())
+    }
+}
+
+// Rows of files/PageFilesModule
+pub fn parse_files(body: &str) -> Result<Vec<PageFile>, WikidotError>{
+    let body = Html::parse_fragment(body);
+
+    let mut file_vec = Vec::new();
+    for row in body.select(&selectors::FILEROW){
+        let id = row.attr("id").ok_or(ParseElementError::file_id())?
+            .replace("file-row-", "")
+            .parse::<i32>()?;
+        let a_ele = row.select(&selectors::A).next().ok_or(ParseElementError::file_ele())?;
+
+        file_vec.push(PageFile{
+            id,
+            name: a_ele.text().collect::<String>().trim().to_string(),
+            url: a_ele.attr("href").ok_or(ParseElementError::file_ele())?.to_string(),
+        });
+    }
+
+    Ok(file_vec)
+}
+
+// The upload target answers with a page that hands its status and message to the parent frame
+pub fn upload_status(text: &str) -> Result<(), WikidotError>{
+    let status_re = Regex::new(r#"status\W*["'](\w+)["']"#)?;
+    let status = status_re.captures(text)
+        .and_then(|captures| captures.get(1))
+        .ok_or(ParseElementError::file_status())?
+        .as_str();
+
+    if status != "ok" {
+        let message_re = Regex::new(r#"message\W*["']([^"']*)["']"#)?;
+        let message = message_re.captures(text)
+            .and_then(|captures| captures.get(1))
+            .map_or(status, |message| message.as_str());
+        Err(FileActionError::new("Upload", message))?
+    }
+
+    Ok(())
+}
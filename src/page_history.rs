@@ -2,7 +2,7 @@ use mongodb::bson::DateTime;
 use regex::Regex;
 use scraper::Html;
 use serde::{Deserialize, Serialize};
-use crate::{error::{ParseElementError, WikidotError}, page::Page, parser, selectors, user::User};
+use crate::{error::{ParseElementError, WikidotError}, page::Page, parser, selectors, site::Site, user::User};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Revision{
@@ -34,8 +34,8 @@ impl Page {
         let body = Html::parse_fragment(&response.body);
 
         let mut revision_vec = Vec::new();
+        let rev_id_re = Regex::new(r"\d+")?;
         for revision in body.select(&selectors::TR).skip(1){
-            let rev_id_re = Regex::new(r"\d+")?;
             let id = rev_id_re.captures(revision.attr("id").ok_or(ParseElementError::revision_id())?)
                 .ok_or(ParseElementError::revision_id())?
                 .get(0).ok_or(ParseElementError::revision_id())?
@@ -67,4 +67,22 @@ impl Page {
 
         Ok(revision_vec)
     }
+}
+
+impl Site {
+    // Revision ids come from the archived history, so no Page is needed
+    pub async fn revision_source(&self, revision_id: i32) -> Result<String, WikidotError>{
+        let response = self.request(&[
+            ("revision_id", &revision_id.to_string()),
+            ("moduleName", "history/PageSourceModule"),
+        ]).await?;
+
+        let body = Html::parse_fragment(&response.body);
+
+        Ok(
+            body.select(&selectors::PAGESOURCE).next().ok_or(ParseElementError::revision_ele())?
+            .text().collect::<String>()
+            .trim().to_string()
+        )
+    }
 }
\ No newline at end of file
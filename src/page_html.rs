@@ -0,0 +1,433 @@
+use std::collections::{HashMap, HashSet};
+use futures::TryStreamExt;
+use mongodb::bson::doc;
+use serde::Deserialize;
+use crate::{error::WikidotError, mongo_page::MongoPage, mongo_revision::{revision_source, MongoRevisionSource}, page_links::{to_fullname, PageLinks}, page_text::plain_text, wikitext::{self, Attributes, CellAlign, LinkKind, Node, NodeKind, Style}};
+
+// Components including themselves (directly or not) stop expanding at this depth
+const INCLUDE_DEPTH_LIMIT: usize = 5;
+const DOCUMENT_STYLE: &str = "body{max-width:60em;margin:2em auto;padding:0 1em;font-family:sans-serif;line-height:1.6}\
+table.wiki-content-table{border-collapse:collapse}table.wiki-content-table td,table.wiki-content-table th{border:1px solid #888;padding:.2em .5em}\
+.code{background:#f4f4f4;border:1px dashed #aaa;padding:.5em;overflow:auto}.yui-nav{list-style:none;padding:0}.yui-nav li{display:inline;margin-right:1em}\
+blockquote{border-left:3px solid #aaa;margin-left:0;padding-left:1em}";
+
+#[derive(Deserialize)]
+struct MongoPageArchive{
+    id: i32,
+    fullname: String,
+    title: String,
+    source: String,
+    #[serde(default)]
+    history: Vec<MongoRevisionArchive>,
+}
+
+#[derive(Deserialize)]
+struct MongoRevisionArchive{
+    index: i16,
+    id: i32,
+}
+
+pub struct HtmlOptions<'a>{
+    pub fullname: &'a str,
+    // Page links become `{page_base}{fullname}{page_suffix}`
+    pub page_base: &'a str,
+    pub page_suffix: &'a str,
+    // Files are not archived, so this has to serve the live ones, e.g. https://{site}.wikidot.com/local--files/
+    pub file_base: &'a str,
+    pub includes: &'a HashMap<String, String>,
+}
+
+pub fn escape(text: &str) -> String{
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars(){
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Key under which an include target is looked up in `HtmlOptions::includes`
+pub fn include_key(target: &str) -> Option<String>{
+    let target = target.trim();
+    if target.starts_with(':') {Some(target.to_lowercase())}
+    else {to_fullname(target)}
+}
+
+pub fn render_html(source: &str, options: &HtmlOptions) -> String{
+    let nodes = wikitext::parse(source);
+    let mut renderer = HtmlRenderer{options, out: String::new(), footnotes: Vec::new(), footnotes_placed: false, toc: Vec::new(), heading_count: 0, tab_count: 0, depth: 0};
+    wikitext::walk(&nodes, &mut |node| {
+        if let NodeKind::Heading{level, toc: true, children} = &node.kind {
+            renderer.toc.push((*level, plain_text(children)));
+        }
+    });
+    renderer.blocks(&nodes);
+    if !renderer.footnotes_placed {
+        renderer.footnote_block();
+    }
+    renderer.out
+}
+
+pub fn render_document(title: &str, source: &str, options: &HtmlOptions) -> String{
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\" />\n<title>{title}</title>\n<style>{DOCUMENT_STYLE}</style>\n</head>\n<body>\n<div id=\"page-title\">{title}</div>\n<div id=\"page-content\">\n{content}\n</div>\n</body>\n</html>\n",
+        title = escape(title),
+        content = render_html(source, options),
+    )
+}
+
+// Renders a live page from the archive, or one of its revisions if that revision's source was archived
+pub async fn render_archived_page(collection: mongodb::Collection<MongoPage>, revision_col: mongodb::Collection<MongoRevisionSource>, fullname: &str, revision: Option<i16>, page_base: &str, page_suffix: &str, file_base: &str) -> Result<Option<String>, WikidotError>{
+    let collection = collection.clone_with_type::<MongoPageArchive>();
+    let page = collection.find_one(doc! {"fullname": fullname, "status": true})
+        .projection(doc! {"id": 1, "fullname": 1, "title": 1, "source": 1, "history.index": 1, "history.id": 1})
+        .await?;
+    let Some(page) = page else {
+        return Ok(None)
+    };
+    let source = match revision {
+        Some(index) => {
+            let Some(revision) = page.history.iter().find(|rev| rev.index == index) else { return Ok(None) };
+            match revision_source(revision_col, page.id, revision.id).await? {
+                Some(source) => source,
+                None => return Ok(None),
+            }
+        },
+        None => page.source,
+    };
+
+    let mut includes = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![source.clone()];
+    for _ in 0..INCLUDE_DEPTH_LIMIT{
+        let targets = pending.iter()
+            .flat_map(|source| PageLinks::from_source(source).includes)
+            .filter_map(|include| include_key(&include.target))
+            .filter(|key| !key.starts_with(':') && seen.insert(key.clone()))
+            .collect::<Vec<_>>();
+        if targets.is_empty() {
+            break
+        }
+        pending.clear();
+        let mut cursor = collection.find(doc! {"fullname": {"$in": targets}, "status": true}).projection(doc! {"id": 1, "fullname": 1, "title": 1, "source": 1}).await?;
+        while let Some(component) = cursor.try_next().await? {
+            pending.push(component.source.clone());
+            includes.insert(component.fullname, component.source);
+        }
+    }
+
+    let options = HtmlOptions{fullname: &page.fullname, page_base, page_suffix, file_base, includes: &includes};
+    Ok(Some(render_document(&page.title, &source, &options)))
+}
+
+struct HtmlRenderer<'a, 'b>{
+    options: &'b HtmlOptions<'a>,
+    out: String,
+    footnotes: Vec<String>,
+    footnotes_placed: bool,
+    toc: Vec<(u8, String)>,
+    heading_count: usize,
+    tab_count: usize,
+    depth: usize,
+}
+
+impl HtmlRenderer<'_, '_>{
+    fn blocks(&mut self, nodes: &[Node]){
+        for node in nodes{
+            self.node(node);
+        }
+    }
+
+    fn node(&mut self, node: &Node){
+        match &node.kind {
+            NodeKind::Text(text) | NodeKind::Raw(text) => self.out.push_str(&escape(text)),
+            NodeKind::LineBreak => self.out.push_str("<br />\n"),
+            NodeKind::Paragraph(children) => self.wrap("<p>", children, "</p>\n"),
+            NodeKind::Heading{level, toc, children} => {
+                if *toc && self.depth == 0 {
+                    self.out.push_str(&format!("<h{level} id=\"toc{}\"><span>", self.heading_count));
+                    self.heading_count += 1;
+                }
+                else {
+                    self.out.push_str(&format!("<h{level}><span>"));
+                }
+                self.blocks(children);
+                self.out.push_str(&format!("</span></h{level}>\n"));
+            },
+            NodeKind::HorizontalRule => self.out.push_str("<hr />\n"),
+            NodeKind::List{ordered, items} => {
+                let tag = if *ordered {"ol"} else {"ul"};
+                self.out.push_str(&format!("<{tag}>\n"));
+                for item in items{
+                    self.wrap("<li>", item.children(), "</li>\n");
+                }
+                self.out.push_str(&format!("</{tag}>\n"));
+            },
+            NodeKind::ListItem(children) => self.wrap("<li>", children, "</li>\n"),
+            NodeKind::Quote(children) => self.wrap("<blockquote>\n", children, "\n</blockquote>\n"),
+            NodeKind::Table(rows) => self.wrap("<table class=\"wiki-content-table\">\n", rows, "</table>\n"),
+            NodeKind::TableRow(cells) => self.wrap("<tr>\n", cells, "</tr>\n"),
+            NodeKind::TableCell{header, align, colspan, children} => {
+                let tag = if *header {"th"} else {"td"};
+                self.out.push('<');
+                self.out.push_str(tag);
+                if *colspan > 1 {
+                    self.out.push_str(&format!(" colspan=\"{colspan}\""));
+                }
+                match align {
+                    Some(CellAlign::Left) => self.out.push_str(" style=\"text-align: left;\""),
+                    Some(CellAlign::Right) => self.out.push_str(" style=\"text-align: right;\""),
+                    Some(CellAlign::Center) => self.out.push_str(" style=\"text-align: center;\""),
+                    None => (),
+                }
+                self.out.push('>');
+                self.blocks(children);
+                self.out.push_str(&format!("</{tag}>\n"));
+            },
+            NodeKind::Format{style, children} => {
+                let (open, close) = match style {
+                    Style::Bold => ("<strong>", "</strong>"),
+                    Style::Italic => ("<em>", "</em>"),
+                    Style::Underline => ("<span style=\"text-decoration: underline;\">", "</span>"),
+                    Style::Strikethrough => ("<span style=\"text-decoration: line-through;\">", "</span>"),
+                    Style::Superscript => ("<sup>", "</sup>"),
+                    Style::Subscript => ("<sub>", "</sub>"),
+                    Style::Monospace => ("<tt>", "</tt>"),
+                };
+                self.wrap(open, children, close);
+            },
+            NodeKind::Color{color, children} => {
+                let open = format!("<span style=\"color: {};\">", escape(color));
+                self.wrap(&open, children, "</span>");
+            },
+            NodeKind::Comment(_) => (),
+            NodeKind::Link{target, text, kind, new_window} => {
+                let href = match kind {
+                    LinkKind::Page => match target.split_once('#') {
+                        Some((page, anchor)) => format!("{}{}{}#{}", self.options.page_base, to_fullname(page).unwrap_or_default(), self.options.page_suffix, anchor),
+                        None => format!("{}{}{}", self.options.page_base, to_fullname(target).unwrap_or_default(), self.options.page_suffix),
+                    },
+                    LinkKind::Url | LinkKind::Anchor => safe_href(target).to_string(),
+                };
+                let text = text.as_deref().unwrap_or(target.trim_start_matches('/'));
+                let blank = if *new_window {" target=\"_blank\""} else {""};
+                self.out.push_str(&format!("<a href=\"{}\"{blank}>{}</a>", escape(&href), escape(text)));
+            },
+            NodeKind::Image{source, align, attributes} => self.image(source, align.as_deref(), attributes),
+            NodeKind::Anchor(name) => self.out.push_str(&format!("<a name=\"{}\"></a>", escape(name))),
+            NodeKind::User{name, ..} => self.out.push_str(&format!("<span class=\"printuser\">{}</span>", escape(name))),
+            NodeKind::Math(math) => self.out.push_str(&format!("<span class=\"math-inline\">${}$</span>", escape(math))),
+            NodeKind::Include{target, variables} => self.include(target, variables),
+            NodeKind::Module{name, body, ..} => {
+                if name.eq_ignore_ascii_case("css") {
+                    // Only guards against closing the style element early
+                    let css = body.as_deref().unwrap_or("").replace("</", "<\\/");
+                    self.out.push_str(&format!("<style>{css}</style>\n"));
+                }
+                else {
+                    self.out.push_str(&format!("<div class=\"module\" data-module=\"{}\"></div>\n", escape(name)));
+                }
+            },
+            NodeKind::RawBlock{name, content, ..} => match name.as_str() {
+                "code" => self.out.push_str(&format!("<div class=\"code\"><pre><code>{}</code></pre></div>\n", escape(content.trim_end()))),
+                "math" => self.out.push_str(&format!("<div class=\"math-equation\">{}</div>\n", escape(content.trim()))),
+                "html" => self.out.push_str(&format!("<iframe class=\"html-block-iframe\" sandbox=\"\" srcdoc=\"{}\"></iframe>\n", escape(content))),
+                _ => (),
+            },
+            NodeKind::Footnote(children) => {
+                let mut renderer = self.child(self.depth);
+                renderer.blocks(children);
+                let number = self.footnotes.len() + 1;
+                self.footnotes.push(renderer.out);
+                self.out.push_str(&format!("<sup class=\"footnoteref\"><a id=\"footnoteref-{number}\" href=\"#footnote-{number}\" class=\"footnoteref\">{number}</a></sup>"));
+            },
+            NodeKind::Element{name, attributes, children} => self.element(name, attributes, children),
+        }
+    }
+
+    fn wrap(&mut self, open: &str, children: &[Node], close: &str){
+        self.out.push_str(open);
+        self.blocks(children);
+        self.out.push_str(close);
+    }
+
+    fn child<'c>(&'c self, depth: usize) -> HtmlRenderer<'c, 'c>{
+        HtmlRenderer{options: self.options, out: String::new(), footnotes: Vec::new(), footnotes_placed: true, toc: Vec::new(), heading_count: 0, tab_count: 0, depth}
+    }
+
+    fn file_url(&self, source: &str) -> String{
+        if source.contains("://") {
+            source.to_string()
+        }
+        else if let Some(path) = source.strip_prefix('/') {
+            format!("{}{}", self.options.file_base, path)
+        }
+        else {
+            format!("{}{}/{}", self.options.file_base, self.options.fullname, source)
+        }
+    }
+
+    fn image(&mut self, source: &str, align: Option<&str>, attributes: &Attributes){
+        let mut img = format!("<img src=\"{}\" alt=\"{}\" class=\"image\"", escape(&self.file_url(source)), escape(attributes.get("alt").unwrap_or(source)));
+        for key in ["width", "height", "style", "class", "title"] {
+            if let Some(value) = attributes.get(key) {
+                img.push_str(&format!(" {key}=\"{}\"", escape(value)));
+            }
+        }
+        img.push_str(" />");
+        if let Some(link) = attributes.get("link") {
+            img = format!("<a href=\"{}\">{img}</a>", escape(safe_href(link)));
+        }
+        match align {
+            Some("=") => self.out.push_str(&format!("<div class=\"image-container aligncenter\">{img}</div>")),
+            Some("<") | Some("f<") => self.out.push_str(&format!("<div class=\"image-container alignleft\" style=\"float: left;\">{img}</div>")),
+            Some(">") | Some("f>") => self.out.push_str(&format!("<div class=\"image-container alignright\" style=\"float: right;\">{img}</div>")),
+            _ => self.out.push_str(&img),
+        }
+    }
+
+    fn include(&mut self, target: &str, variables: &[(String, String)]){
+        let source = include_key(target).and_then(|key| self.options.includes.get(&key));
+        match source {
+            Some(source) if self.depth < INCLUDE_DEPTH_LIMIT => {
+                let mut source = source.clone();
+                for (key, value) in variables{
+                    source = source.replace(&format!("{{${key}}}"), value);
+                }
+                let nodes = wikitext::parse(&source);
+                let mut renderer = self.child(self.depth + 1);
+                renderer.blocks(&nodes);
+                let HtmlRenderer{out, footnotes, ..} = renderer;
+                self.out.push_str(&out);
+                self.footnotes.extend(footnotes);
+            },
+            _ => self.out.push_str(&format!("<div class=\"error-block\">Included page \"{}\" is not archived</div>\n", escape(target.trim()))),
+        }
+    }
+
+    fn footnote_block(&mut self){
+        self.footnotes_placed = true;
+        if self.footnotes.is_empty() {
+            return
+        }
+        self.out.push_str("<div class=\"footnotes-footer\">\n<div class=\"title\">Footnotes</div>\n");
+        for (i, footnote) in self.footnotes.iter().enumerate(){
+            let number = i + 1;
+            self.out.push_str(&format!("<div class=\"footnote-footer\" id=\"footnote-{number}\"><a href=\"#footnoteref-{number}\">{number}</a>. {footnote}</div>\n"));
+        }
+        self.out.push_str("</div>\n");
+    }
+
+    fn table_of_contents(&mut self){
+        self.out.push_str("<div id=\"toc\">\n<div class=\"title\">Table of Contents</div>\n<div id=\"toc-list\">\n");
+        for (i, (level, text)) in self.toc.iter().enumerate(){
+            self.out.push_str(&format!("<div style=\"margin-left: {}em;\"><a href=\"#toc{i}\">{}</a></div>\n", (level - 1) * 2, escape(text)));
+        }
+        self.out.push_str("</div>\n</div>\n");
+    }
+
+    fn element(&mut self, name: &str, attributes: &Attributes, children: &[Node]){
+        match name {
+            "div" | "div_" | "span" => {
+                let tag = if name == "span" {"span"} else {"div"};
+                self.out.push_str(&format!("<{tag}{}>", safe_attributes(attributes)));
+                self.blocks(children);
+                self.out.push_str(&format!("</{tag}>"));
+            },
+            "size" => {
+                let open = format!("<span style=\"font-size: {};\">", escape(&attributes.raw));
+                self.wrap(&open, children, "</span>");
+            },
+            "a" => {
+                let open = format!("<a href=\"{}\"{}>", escape(safe_href(attributes.get("href").unwrap_or("#"))), safe_attributes(attributes));
+                self.wrap(&open, children, "</a>");
+            },
+            "collapsible" => {
+                let show = attributes.get("show").unwrap_or("+ show block");
+                let hide = attributes.get("hide").unwrap_or("- hide block");
+                let folded = attributes.get("folded") != Some("no");
+                self.out.push_str(&format!(
+                    "<details class=\"collapsible-block\"{}><summary class=\"collapsible-block-link\" data-show=\"{}\" data-hide=\"{}\">{}</summary>\n<div class=\"collapsible-block-content\">\n",
+                    if folded {""} else {" open"}, escape(show), escape(hide), escape(if folded {show} else {hide}),
+                ));
+                self.blocks(children);
+                self.out.push_str("</div>\n</details>\n");
+            },
+            "tabview" | "tabs" => {
+                let view = self.tab_count;
+                self.tab_count += 1;
+                let tabs = children.iter()
+                    .filter_map(|child| match &child.kind {
+                        NodeKind::Element{name, attributes, children} if name == "tab" => Some((attributes.raw.as_str(), children)),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                self.out.push_str("<div class=\"yui-navset\">\n<ul class=\"yui-nav\">\n");
+                for (i, (title, _)) in tabs.iter().enumerate(){
+                    self.out.push_str(&format!("<li><a href=\"#wiki-tab-{view}-{i}\"><em>{}</em></a></li>\n", escape(title)));
+                }
+                self.out.push_str("</ul>\n<div class=\"yui-content\">\n");
+                for (i, (_, tab_children)) in tabs.iter().enumerate(){
+                    self.out.push_str(&format!("<div id=\"wiki-tab-{view}-{i}\">\n"));
+                    self.blocks(tab_children);
+                    self.out.push_str("</div>\n");
+                }
+                self.out.push_str("</div>\n</div>\n");
+            },
+            "=" | "==" => self.wrap("<div style=\"text-align: center;\">\n", children, "</div>\n"),
+            "<" => self.wrap("<div style=\"text-align: left;\">\n", children, "</div>\n"),
+            ">" => self.wrap("<div style=\"text-align: right;\">\n", children, "</div>\n"),
+            "table" | "row" | "cell" | "hcell" | "ul" | "ol" | "li" => {
+                let tag = match name {
+                    "table" => "table",
+                    "row" => "tr",
+                    "cell" => "td",
+                    "hcell" => "th",
+                    tag => tag,
+                };
+                self.out.push_str(&format!("<{tag}{}>", safe_attributes(attributes)));
+                self.blocks(children);
+                self.out.push_str(&format!("</{tag}>\n"));
+            },
+            "note" => self.wrap("<div class=\"wiki-note\">\n", children, "</div>\n"),
+            "footnoteblock" => self.footnote_block(),
+            "toc" | "f<toc" | "f>toc" => self.table_of_contents(),
+            "newline" => self.out.push_str("<br />"),
+            _ => self.blocks(children),
+        }
+    }
+}
+
+// Relative links and known schemes only, browsers skip whitespace and control characters inside a scheme
+fn safe_href(href: &str) -> &str{
+    let before_path = href.split(['/', '?', '#']).next().unwrap_or_default();
+    match before_path.split_once(':') {
+        Some((scheme, _)) => {
+            let scheme = scheme.chars()
+                .filter(|c| !c.is_whitespace() && !c.is_control())
+                .collect::<String>()
+                .to_lowercase();
+            if ["http", "https", "mailto", "ftp"].contains(&scheme.as_str()) {href} else {"#"}
+        },
+        None => href,
+    }
+}
+
+fn safe_attributes(attributes: &Attributes) -> String{
+    let mut safe = String::new();
+    for (key, value) in &attributes.values{
+        match key.as_str() {
+            "class" | "style" | "title" | "lang" | "dir" => safe.push_str(&format!(" {key}=\"{}\"", escape(value))),
+            // Wikidot prefixes user-defined ids so they cannot clash with its own
+            "id" => safe.push_str(&format!(" id=\"u-{}\"", escape(value.trim_start_matches("u-")))),
+            _ => (),
+        }
+    }
+    safe
+}
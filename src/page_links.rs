@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use crate::{parser, wikitext::{self, LinkKind, NodeKind}};
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct PageInclude{
+    pub target: String,
+    pub variables: Vec<(String, String)>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, Debug, PartialEq)]
+pub struct PageLinks{
+    pub pages: Vec<String>,
+    pub urls: Vec<String>,
+    pub includes: Vec<PageInclude>,
+    pub images: Vec<String>,
+}
+
+// Turns a link target such as "Level 0#top" or "/level-0/offset/2" into a page fullname
+pub fn to_fullname(target: &str) -> Option<String>{
+    let target = target.trim().trim_start_matches('/');
+    let target = target.split(['#', '?', '/']).next()?;
+
+    let fullname = parser::to_unix_name(target)
+        .split(':')
+        .map(|part| part.trim_matches('-'))
+        .collect::<Vec<_>>()
+        .join(":");
+    let fullname = fullname.strip_prefix("_default:").unwrap_or(&fullname).trim_matches(':');
+
+    (!fullname.is_empty()).then(|| fullname.to_string())
+}
+
+impl PageLinks{
+    pub fn from_source(source: &str) -> Self{
+        let mut links = PageLinks::default();
+        wikitext::walk(&wikitext::parse(source), &mut |node| match &node.kind {
+            NodeKind::Link{target, kind: LinkKind::Page, ..} => links.push_page(target),
+            NodeKind::Link{target, kind: LinkKind::Url, ..} => links.push_url(target),
+            NodeKind::Element{name, attributes, ..} if name == "a" => {
+                if let Some(href) = attributes.get("href") {
+                    if href.starts_with('/') {links.push_page(href)}
+                    else if href.contains("://") {links.push_url(href)}
+                }
+            },
+            NodeKind::Include{target, variables} => links.includes.push(PageInclude{
+                target: target.trim().to_lowercase(),
+                variables: variables.clone(),
+            }),
+            NodeKind::Image{source, ..} if !links.images.contains(source) => links.images.push(source.clone()),
+            _ => (),
+        });
+        links
+    }
+
+    fn push_page(&mut self, target: &str){
+        if let Some(fullname) = to_fullname(target) {
+            if !self.pages.contains(&fullname) {
+                self.pages.push(fullname);
+            }
+        }
+    }
+
+    fn push_url(&mut self, url: &str){
+        if !self.urls.iter().any(|existing| existing == url) {
+            self.urls.push(url.to_string());
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use scraper::Html;
 use serde::{Deserialize, Serialize};
-use crate::{error::WikidotError, page::Page, parser, selectors, user::User};
+use crate::{error::WikidotError, page::Page, parser, selectors, site::Site, user::User};
 
 #[derive(Serialize, Deserialize)]
 pub struct RateUser{
@@ -11,8 +11,14 @@ pub struct RateUser{
 impl Page {
     pub async fn acquire_votes(&mut self) -> Result<Vec<RateUser>, WikidotError>{
         let page_id = self.acquire_id().await?;
+        self.site.page_votes(page_id).await
+    }
+}
 
-        let response = self.site.request(&[
+impl Site {
+    // For pages whose id is already archived, without a listing entry to build a Page from
+    pub async fn page_votes(&self, page_id: i32) -> Result<Vec<RateUser>, WikidotError>{
+        let response = self.request(&[
             ("pageId", &page_id.to_string()),
             ("moduleName", "pagerate/WhoRatedPageModule")
             ]).await?;
@@ -21,7 +27,7 @@ impl Page {
 
         let mut rate_vec = Vec::new();
 
-        for (user_ele, vote_ele) in body.select(&selectors::PRINTUSER).into_iter().zip(body.select(&selectors::VOTE)){
+        for (user_ele, vote_ele) in body.select(&selectors::PRINTUSER).zip(body.select(&selectors::VOTE)){
             rate_vec.push(RateUser{
                 user: parser::printuser(user_ele)?,
                 rate: match vote_ele.text().collect::<String>(){
@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use crate::wikitext::{self, LinkKind, Node, NodeKind};
+
+const WORDS_PER_MINUTE: f64 = 200.0;
+const CJK_PER_MINUTE: f64 = 300.0;
+
+#[derive(Clone, Default, Deserialize, Serialize, Debug, PartialEq)]
+pub struct PageText{
+    pub text: String,
+    pub words: i32,
+    pub characters: i32,
+    pub cjk_characters: i32,
+    pub reading_time: i32,
+}
+
+pub fn is_cjk(c: char) -> bool{
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2FA1F}'
+    )
+}
+
+pub fn plain_text(nodes: &[Node]) -> String{
+    let mut renderer = TextRenderer::default();
+    renderer.blocks(nodes);
+    if !renderer.footnotes.is_empty() {
+        let footnotes = std::mem::take(&mut renderer.footnotes);
+        renderer.out.truncate(renderer.out.trim_end().len());
+        renderer.out.push_str("\n\n");
+        renderer.out.push_str(&footnotes.join("\n"));
+    }
+    renderer.out.trim().to_string()
+}
+
+impl PageText{
+    pub fn from_source(source: &str) -> Self{
+        let text = plain_text(&wikitext::parse(source));
+
+        let mut words = 0;
+        let mut characters = 0;
+        let mut cjk_characters = 0;
+        let mut in_word = false;
+        for c in text.chars(){
+            if c.is_whitespace() {
+                in_word = false;
+                continue;
+            }
+            characters += 1;
+            if is_cjk(c) {
+                cjk_characters += 1;
+                in_word = false;
+            }
+            else if c.is_alphanumeric() {
+                if !in_word {
+                    words += 1;
+                }
+                in_word = true;
+            }
+            else if !['\'', '-', '’'].contains(&c) {
+                in_word = false;
+            }
+        }
+        let minutes = words as f64 / WORDS_PER_MINUTE + cjk_characters as f64 / CJK_PER_MINUTE;
+
+        PageText{
+            text,
+            words,
+            characters,
+            cjk_characters,
+            reading_time: (minutes * 60.0).round() as i32,
+        }
+    }
+}
+
+#[derive(Default)]
+struct TextRenderer{
+    out: String,
+    footnotes: Vec<String>,
+}
+
+impl TextRenderer{
+    fn block_break(&mut self){
+        let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(trimmed);
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push_str(if self.out.ends_with('\n') {"\n"} else {"\n\n"});
+        }
+    }
+
+    fn blocks(&mut self, nodes: &[Node]){
+        for node in nodes{
+            match &node.kind {
+                NodeKind::Paragraph(children) | NodeKind::Heading{children, ..} => {
+                    self.block_break();
+                    self.inline(children);
+                    self.block_break();
+                },
+                NodeKind::List{items, ..} => {
+                    self.block_break();
+                    self.list(items);
+                    self.block_break();
+                },
+                NodeKind::Table(rows) => {
+                    self.block_break();
+                    for row in rows{
+                        let cells = row.children().iter()
+                            .map(|cell| {
+                                let mut renderer = TextRenderer::default();
+                                renderer.inline(cell.children());
+                                self.footnotes.append(&mut renderer.footnotes);
+                                renderer.out.trim().to_string()
+                            })
+                            .collect::<Vec<_>>();
+                        self.out.push_str(&cells.join("\t"));
+                        self.out.push('\n');
+                    }
+                    self.block_break();
+                },
+                NodeKind::Quote(children) => {
+                    self.block_break();
+                    self.inline(children);
+                    self.block_break();
+                },
+                NodeKind::RawBlock{name, content, ..} if name == "code" => {
+                    self.block_break();
+                    self.out.push_str(content.trim_end());
+                    self.block_break();
+                },
+                NodeKind::Element{name, children, ..} if is_block_element(name) => {
+                    self.block_break();
+                    self.blocks(children);
+                    self.block_break();
+                },
+                _ => self.inline(std::slice::from_ref(node)),
+            }
+        }
+    }
+
+    fn list(&mut self, items: &[Node]){
+        for item in items{
+            let (nested, inline): (Vec<&Node>, Vec<&Node>) = item.children().iter()
+                .partition(|child| matches!(child.kind, NodeKind::List{..}));
+            for child in inline{
+                self.inline(std::slice::from_ref(child));
+            }
+            self.out.push('\n');
+            for list in nested{
+                self.list(list.children());
+            }
+        }
+    }
+
+    fn inline(&mut self, nodes: &[Node]){
+        for node in nodes{
+            match &node.kind {
+                NodeKind::Text(text) | NodeKind::Raw(text) => self.out.push_str(text),
+                NodeKind::LineBreak => self.out.push('\n'),
+                NodeKind::Link{text: Some(text), ..} => self.out.push_str(text),
+                NodeKind::Link{target, text: None, kind: LinkKind::Page, ..} => self.out.push_str(target),
+                NodeKind::User{name, ..} => self.out.push_str(name),
+                NodeKind::Math(math) => self.out.push_str(math),
+                NodeKind::Footnote(children) => {
+                    let mut renderer = TextRenderer::default();
+                    renderer.inline(children);
+                    self.footnotes.push(format!("{}. {}", self.footnotes.len() + 1, renderer.out.trim()));
+                },
+                NodeKind::Format{children, ..}
+                | NodeKind::Color{children, ..}
+                | NodeKind::Quote(children) => self.inline(children),
+                NodeKind::Element{name, children, ..} if is_block_element(name) => self.blocks(children),
+                NodeKind::Element{children, ..} => self.inline(children),
+                NodeKind::Paragraph(_)
+                | NodeKind::Heading{..}
+                | NodeKind::List{..}
+                | NodeKind::Table(_) => self.blocks(std::slice::from_ref(node)),
+                NodeKind::RawBlock{name, ..} if name == "code" => self.blocks(std::slice::from_ref(node)),
+                _ => (),
+            }
+        }
+    }
+}
+
+fn is_block_element(name: &str) -> bool{
+    !["span", "size", "a", "footnote"].contains(&name)
+}
@@ -40,12 +40,23 @@ pub fn printuser(element: ElementRef) -> Result<User, WikidotError>{
 
         Ok(User::from_deleted_user(Some(user_id)))
     }
-    else if element.text().collect::<String>() == "Wikidot"{
+    else if ele_val.value().classes().collect::<Vec<&str>>().contains(&"anonymous"){
+        let ip_re = Regex::new(r"anonymousUserInfo\('([^']+)'\)")?;
+        let ip = ele_val.select(&selectors::A)
+            .filter_map(|a_ele| a_ele.attr("onclick"))
+            .find_map(|onclick| ip_re.captures(onclick))
+            .ok_or(ParseElementError::parser_ip())?
+            .get(1).ok_or(ParseElementError::parser_ip())?
+            .as_str().to_string();
+
+        Ok(User::from_anonymous_user(ip))
+    }
+    else if ele_val.text().collect::<String>() == "Wikidot"{
         Ok(User::from_wikidot_user())
     }
     else{
         let id_re = Regex::new(r"\((\d+)\)")?;
-        let mut a_eles = element.select(&selectors::A);
+        let mut a_eles = ele_val.select(&selectors::A);
 
         if let Some(a_ele_2) = a_eles.nth(1){
             let user_id = id_re.captures(a_ele_2.attr("onclick").ok_or(ParseElementError::parser_id())?)
@@ -63,12 +74,38 @@ pub fn printuser(element: ElementRef) -> Result<User, WikidotError>{
             ))
         }
         else{
-            Ok(User::from_guest_user(element.text().collect::<String>()))
+            Ok(User::from_guest_user(ele_val.text().collect::<String>()))
+        }
+    }
+}
+
+// Wikidot's unix name rule, shared by page fullnames and user names
+// Same as Wikidot, an underscore only survives at the start of a name or a category
+pub fn to_unix_name(text: &str) -> String{
+    let mut unix_name = String::new();
+    for c in text.trim().to_lowercase().chars(){
+        if c == '_' && (unix_name.is_empty() || unix_name.ends_with(':')) {
+            unix_name.push(c)
+        }
+        else if c == ':' {
+            if unix_name.ends_with('-') {
+                unix_name.pop();
+            }
+            if !unix_name.ends_with(':') {
+                unix_name.push(c)
+            }
+        }
+        else if c.is_ascii_alphanumeric() {
+            unix_name.push(c)
+        }
+        else if !unix_name.ends_with('-') && !unix_name.ends_with(':') {
+            unix_name.push('-')
         }
     }
+    unix_name.trim_matches(|c| c == '-' || c == ':').to_string()
 }
 
-fn user_add(user_id: i32) {
+pub fn user_add(user_id: i32) {
     let add_vec = USER_ADD.lock().unwrap();
     let now_vec = USER_NOW.lock().unwrap();
     if !add_vec.contains(&user_id) & !now_vec.contains(&user_id) {
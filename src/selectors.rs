@@ -29,4 +29,13 @@ build_selectors!(
     (KEY, "span.name"),
     (VALUE, "span.value"),
     (TABLE, "table.wiki-content-table"),
+    (FILEROW, "tr[id^='file-row-']"),
+    (PROFILETITLE, "h1.profile-title"),
+    (PROFILEBTN, "a.btn.btn-default.btn-xs"),
+    (ERRORBLOCK, "div.error-block"),
+    (CHANGEITEM, "div.changes-list-item"),
+    (CHANGETITLE, "td.title a"),
+    (CHANGEFLAGS, "td.flags"),
+    (CHANGEREV, "td.revision-no"),
+    (CHANGECOMMENT, "div.comments"),
 );
\ No newline at end of file
@@ -5,7 +5,7 @@ use scraper::{selectable::Selectable, Html};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use futures::{stream, StreamExt, TryStreamExt};
-use crate::{client::{AjaxClient, AjaxResponse}, error::{AjaxClientError, ParseElementError, TargetNotExist, WikidotError}, page::Page, parser, selectors};
+use crate::{client::{AjaxActionResponse, AjaxClient, AjaxResponse}, error::{AjaxClientError, ParseElementError, TargetNotExist, WikidotError}, page::Page, parser, selectors};
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Site{
@@ -28,6 +28,11 @@ impl Site{
         self.client.request(param, &format!("{url}/ajax-module-connector.php")).await
     }
 
+    pub async fn action(&self, param: &[(&str, &str)]) -> Result<AjaxActionResponse, AjaxClientError>{
+        let url = &self.url();
+        self.client.action(param, &format!("{url}/ajax-module-connector.php")).await
+    }
+
     pub async fn search(&self, param: &[(&str, &str)]) -> Result<Vec<Page>, WikidotError>{
         let properties = [
             "fullname",
@@ -52,15 +57,14 @@ impl Site{
             "_tags",
         ];
         let mut module_body = String::from("[[div class=\"page\"]]\n");
-        properties.map(|property|
-                module_body.push_str(&format!(
+        for property in properties{
+            module_body.push_str(&format!(
                         r#"[[span class="set {property}"]]
                         [[span class="name"]] {property} [[/span]]
                         [[span class="value"]] %%{property}%% [[/span]]
                         [[/span]]"#
-                )
-            )
-        );
+            ));
+        }
         module_body.push_str("\n[[/div]]");
         let mut param_vec = Vec::from([
             ("moduleName", "list/ListPagesModule"),
@@ -82,9 +86,9 @@ impl Site{
         let mut tasks = Vec::new();
         let module_arc = Arc::new(&module_body);
 
-        for i in 2..page_num{
+        for i in 2..=page_num{
             let module_arc_clone = module_arc.clone();
-            let num = (i * 250).to_string();
+            let num = ((i - 1) * 250).to_string();
             tasks.push(async move{
                 let mut single_vec = Vec::from(&[
                         ("moduleName", "list/ListPagesModule"),
@@ -92,7 +96,7 @@ impl Site{
                         ("perPage", "250"),
                         ("offset", &num)
                     ]);
-                single_vec.extend_from_slice(&param);
+                single_vec.extend_from_slice(param);
                 self.request(&single_vec).await
             })
         }
@@ -0,0 +1,83 @@
+use mongodb::bson::DateTime;
+use regex::Regex;
+use scraper::{ElementRef, Html};
+use serde::{Deserialize, Serialize};
+use crate::{error::{ParseElementError, WikidotError}, parser, selectors, site::Site, user::User};
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct SiteChange{
+    pub fullname: String,
+    pub title: String,
+    pub revision_no: Option<i16>,
+    pub flags: Vec<char>,
+    pub changed_by: User,
+    pub changed_at: Option<DateTime>,
+    pub comment: String,
+}
+
+impl Site{
+    pub async fn recent_changes(&self, since: DateTime) -> Result<Vec<SiteChange>, WikidotError>{
+        let no_re = Regex::new(r"of (\d+)")?;
+        let mut change_vec = Vec::new();
+        let mut page: i16 = 1;
+
+        loop {
+            let result = self.request(&[
+                ("moduleName", "changes/SiteChangesModule"),
+                ("options", "{\"all\": true}"),
+                ("perpage", "100"),
+                ("page", &page.to_string()),
+            ]).await?;
+            let body = Html::parse_fragment(&result.body);
+
+            let page_num = match body.select(&selectors::PAGERNO).next(){
+                Some(val) => no_re.captures(&val.text().collect::<String>()).ok_or(ParseElementError::page_num())?
+                    .get(1).ok_or(ParseElementError::page_num())?
+                    .as_str().parse::<i16>()?,
+                None => 1,
+            };
+
+            let mut reached = false;
+            for item in body.select(&selectors::CHANGEITEM){
+                let change = parse_change(item)?;
+                if change.changed_at.is_some_and(|time| time < since) {
+                    reached = true;
+                    break;
+                }
+                change_vec.push(change);
+            }
+
+            if reached || page >= page_num {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(change_vec)
+    }
+}
+
+// One div.changes-list-item of changes/SiteChangesModule
+pub fn parse_change(item: ElementRef) -> Result<SiteChange, WikidotError>{
+    let rev_re = Regex::new(r"(\d+)")?;
+    let a_ele = item.select(&selectors::CHANGETITLE).next().ok_or(ParseElementError::change_ele())?;
+    let fullname = a_ele.attr("href").ok_or(ParseElementError::change_ele())?
+        .rsplit('/').next().ok_or(ParseElementError::change_ele())?
+        .to_string();
+    let printuser = item.select(&selectors::PRINTUSER).next().ok_or(ParseElementError::change_ele())?;
+
+    Ok(SiteChange{
+        fullname,
+        title: a_ele.text().collect::<String>().trim().to_string(),
+        revision_no: item.select(&selectors::CHANGEREV).next()
+            .and_then(|ele| rev_re.captures(&ele.text().collect::<String>())?.get(1)?.as_str().parse::<i16>().ok()),
+        flags: item.select(&selectors::CHANGEFLAGS).next()
+            .map(|ele| ele.text().collect::<String>().chars().filter(|c| c.is_alphabetic()).collect())
+            .unwrap_or_default(),
+        changed_by: parser::printuser(printuser)?,
+        changed_at: parser::odate(item),
+        comment: item.select(&selectors::CHANGECOMMENT).next()
+            .map(|ele| ele.text().collect::<String>().trim().to_string())
+            .unwrap_or_default(),
+    })
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use futures::{stream, StreamExt, TryStreamExt};
+use mongodb::bson::DateTime;
+use regex::Regex;
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use crate::{error::{ParseElementError, WikidotError}, parser, selectors, site::Site, user::User};
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemberRole{
+    Member,
+    Moderator,
+    Admin,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct SiteMember{
+    pub user: User,
+    pub role: MemberRole,
+    pub joined_at: Option<DateTime>,
+}
+
+impl MemberRole{
+    fn group(&self) -> &str{
+        match self {
+            MemberRole::Member => "",
+            MemberRole::Moderator => "moderators",
+            MemberRole::Admin => "admins",
+        }
+    }
+}
+
+impl Site{
+    pub async fn members(&self) -> Result<Vec<SiteMember>, WikidotError>{
+        let mut member_map: HashMap<i32, SiteMember> = HashMap::new();
+
+        for role in [MemberRole::Member, MemberRole::Moderator, MemberRole::Admin]{
+            for member in self.acquire_member_group(role).await?{
+                let Some(user_id) = member.user.id else { continue };
+                match member_map.get_mut(&user_id) {
+                    Some(existing) => {
+                        existing.role = existing.role.max(member.role);
+                        existing.joined_at = existing.joined_at.or(member.joined_at);
+                    },
+                    None => {member_map.insert(user_id, member);},
+                }
+            }
+        }
+
+        Ok(member_map.into_values().collect())
+    }
+
+    async fn acquire_member_group(&self, role: MemberRole) -> Result<Vec<SiteMember>, WikidotError>{
+        let group = role.group();
+        let result = self.request(&[
+            ("moduleName", "membership/MembersListModule"),
+            ("group", group),
+            ("page", "1"),
+        ]).await?;
+        let fragment = Html::parse_fragment(&result.body);
+        let no_re = Regex::new(r"of (\d+)")?;
+
+        let page_num = match fragment.select(&selectors::PAGERNO).next(){
+            Some(val) => no_re.captures(&val.text().collect::<String>()).ok_or(ParseElementError::page_num())?
+                .get(1).ok_or(ParseElementError::page_num())?
+                .as_str().parse::<i16>()?,
+            None => 1,
+        };
+
+        let mut results = stream::iter((2..=page_num).map(|i| async move {
+            self.request(&[
+                ("moduleName", "membership/MembersListModule"),
+                ("group", group),
+                ("page", &i.to_string()),
+            ]).await
+        }))
+        .buffer_unordered(self.client.config.semaphore_limit as usize)
+        .try_collect::<Vec<_>>().await?
+        .into_iter().map(|x| Html::parse_fragment(&x.body))
+        .collect::<Vec<_>>();
+        results.push(fragment);
+
+        let mut member_vec = Vec::new();
+        for body in results{
+            for tr in body.select(&selectors::TR){
+                let Some(user_ele) = tr.select(&selectors::PRINTUSER).next() else { continue };
+                member_vec.push(SiteMember{
+                    user: parser::printuser(user_ele)?,
+                    role,
+                    joined_at: parser::odate(tr),
+                });
+            }
+        }
+
+        Ok(member_vec)
+    }
+}
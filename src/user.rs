@@ -1,7 +1,8 @@
 use mongodb::bson::DateTime;
+use reqwest::StatusCode;
 use scraper::Html;
 use serde::{Deserialize, Serialize};
-use crate::{client::AjaxClient, error::{ParseElementError, WikidotError}, parser, selectors, site::Site};
+use crate::{client::AjaxClient, error::{ParseElementError, TargetNotExist, WikidotError}, parser, selectors, site::Site};
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct User{
@@ -9,7 +10,21 @@ pub struct User{
     pub name: String,
     pub unix_name: Option<String>,
     pub ip: Option<String>,
-    pub user_type: String,
+    pub user_type: UserType,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum UserType{
+    #[serde(rename = "NormalUser")]
+    Normal,
+    #[serde(rename = "DeletedUser")]
+    Deleted,
+    #[serde(rename = "AnonymousUser")]
+    Anonymous,
+    #[serde(rename = "GuestUser")]
+    Guest,
+    #[serde(rename = "WikidotUser")]
+    Wikidot,
 }
 
 #[derive(Debug)]
@@ -19,6 +34,13 @@ pub struct UserProperty{
     pub avatar: String,
     pub account_type: String,
     pub karma: i8,
+    pub real_name: Option<String>,
+    pub gender: Option<String>,
+    pub birthday: Option<String>,
+    pub location: Option<String>,
+    pub website: Option<String>,
+    pub about: Option<String>,
+    pub pro: Option<bool>,
 }
 
 impl Default for User {
@@ -28,7 +50,7 @@ impl Default for User {
             name: "account deleted".to_string(),
             unix_name: None,
             ip: None,
-            user_type: "DeletedUser".to_string(),
+            user_type: UserType::Deleted,
         }
     }
 }
@@ -38,7 +60,7 @@ impl User{
         User{
             name: "Wikidot".to_string(),
             unix_name: Some("wikidot".to_string()),
-            user_type: "WikidotUser".to_string(),
+            user_type: UserType::Wikidot,
             ..User::default()
         }
     }
@@ -46,7 +68,16 @@ impl User{
     pub fn from_guest_user(name: String) -> Self{
         User{
             name,
-            user_type: "GuestUser".to_string(),
+            user_type: UserType::Guest,
+            ..User::default()
+        }
+    }
+
+    pub fn from_anonymous_user(ip: String) -> Self{
+        User{
+            name: "Anonymous".to_string(),
+            ip: Some(ip),
+            user_type: UserType::Anonymous,
             ..User::default()
         }
     }
@@ -65,7 +96,7 @@ impl User{
             name,
             unix_name: Some(unix_name),
             ip: None,
-            user_type: "NormalUser".to_string(),
+            user_type: UserType::Normal,
         }
     }
 
@@ -76,18 +107,58 @@ impl User{
 
 impl Site{
     pub async fn member_of_site_since(&self, user_id: i32) -> Option<DateTime>{
-        parser::odate(
-            Html::parse_fragment(
+        self.acquire_member_since(user_id).await.ok()
+    }
+
+    pub async fn acquire_member_since(&self, user_id: i32) -> Result<DateTime, WikidotError>{
+        let html = Html::parse_fragment(
             &self.request(&[
                 ("user_id", &user_id.to_string()),
                 ("moduleName", "users/UserInfoWinModule"),
-            ]).await.ok()?.body
-        ).select(&selectors::ODATE)
-        .nth(1)?)
+            ]).await?.body);
+
+        Ok(parser::odate(html.select(&selectors::ODATE).nth(1).ok_or(ParseElementError::user_ele())?)
+            .ok_or(ParseElementError::user_date())?)
     }
 }
 
 impl AjaxClient{
+    pub async fn user_by_name(&self, name: &str) -> Result<User, WikidotError>{
+        self.user_by_unix_name(&parser::to_unix_name(name)).await
+    }
+
+    pub async fn user_by_unix_name(&self, unix_name: &str) -> Result<User, WikidotError>{
+        if let Some(user) = self.users.lock().await.get(unix_name) {
+            return Ok(user.clone())
+        }
+        if unix_name.is_empty() {
+            return Err(TargetNotExist::user())?
+        }
+
+        let _response = self.get(&format!("https://www.wikidot.com/user:info/{unix_name}")).await?;
+        if _response.status() == StatusCode::NOT_FOUND {
+            return Err(TargetNotExist::user())?
+        }
+        let html = Html::parse_document(&_response.text().await?);
+
+        if html.select(&selectors::ERRORBLOCK).next().is_some() {
+            return Err(TargetNotExist::user())?
+        }
+
+        let user_id = html.select(&selectors::PROFILEBTN).next()
+            .ok_or(ParseElementError::user_id())?
+            .attr("href").ok_or(ParseElementError::user_id())?
+            .rsplit('/').next().ok_or(ParseElementError::user_id())?
+            .parse::<i32>()?;
+        let name = html.select(&selectors::PROFILETITLE).next()
+            .ok_or(ParseElementError::user_ele())?
+            .text().collect::<String>().trim().to_string();
+
+        let user = User::from(user_id, name, unix_name.to_string());
+        self.users.lock().await.insert(unix_name.to_string(), user.clone());
+        Ok(user)
+    }
+
     pub async fn user(&self, user_id: i32) -> Result<UserProperty, WikidotError>{
         let html = Html::parse_fragment(
             &self.request(&[
@@ -106,24 +177,42 @@ impl AjaxClient{
             .ok_or(ParseElementError::user_avatar())?.to_str()?.to_string();
         let mut account_type = "free".to_string();
         let mut karma: i8 = 0;
+        let mut real_name = None;
+        let mut gender = None;
+        let mut birthday = None;
+        let mut location = None;
+        let mut website = None;
+        let mut about = None;
         for tr in html.select(&selectors::TR){
-            let mut tds = tr.select(&selectors::TD)
-                .map(|x| x.text().collect::<String>().trim().to_string());
-
-            match tds.next().ok_or(ParseElementError::user_ele())?.as_str(){
-                "Account type" => account_type = if title.is_empty() {"deleted".to_string()} 
-                    else {tds.next().ok_or(ParseElementError::user_ele())?},
-                "Karma level" => karma = match tds.next().ok_or(ParseElementError::user_ele())?[..11].trim() {
-                    "low" => 1,
-                    "medium" => 2,
-                    "high" => 3,
-                    "very high" => 4,
-                    "guru" => 5,
+            let mut tds = tr.select(&selectors::TD);
+            let Some(label) = tds.next() else { continue };
+            let Some(value_ele) = tds.next() else { continue };
+            let value = value_ele.text().collect::<String>().trim().to_string();
+            let optional = if value.is_empty() {None} else {Some(value.clone())};
+
+            match label.text().collect::<String>().trim().trim_end_matches(':'){
+                "Account type" => account_type = if title.is_empty() {"deleted".to_string()} else {value},
+                "Karma level" => karma = match value.to_lowercase() {
+                    val if val.starts_with("very high") => 4,
+                    val if val.starts_with("guru") => 5,
+                    val if val.starts_with("medium") => 2,
+                    val if val.starts_with("high") => 3,
+                    val if val.starts_with("low") => 1,
                     _ => 0,
                 },
+                "Real name" => real_name = optional,
+                "Gender" => gender = optional,
+                "Birthday" => birthday = optional,
+                "From" => location = optional,
+                "Website" => website = value_ele.select(&selectors::A).next()
+                    .and_then(|a| a.attr("href"))
+                    .map(|href| href.to_string())
+                    .or(optional),
+                "About" => about = optional,
                 _ => (),
             }
         }
+        let pro = if account_type == "deleted" {None} else {Some(account_type.to_lowercase().contains("pro"))};
 
         Ok(UserProperty{
             title,
@@ -131,6 +220,13 @@ impl AjaxClient{
             avatar,
             account_type,
             karma,
+            real_name,
+            gender,
+            birthday,
+            location,
+            website,
+            about,
+            pro,
         })
     }
 }
\ No newline at end of file
@@ -0,0 +1,292 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, DateTime};
+use serde::{Deserialize, Serialize};
+use crate::{error::WikidotError, mongo_page::{MongoPage, VoteChange}, mongo_user::MongoUser};
+
+// Pairs need this many identical votes and this Jaccard similarity to be linked
+const CLUSTER_MIN_SHARED: usize = 10;
+const CLUSTER_MIN_SIMILARITY: f64 = 0.8;
+// Votes shared with a crowd say nothing about coordination, and pair counting grows with the crowd squared
+const CLUSTER_MAX_VOTERS: usize = 100;
+const BURST_WINDOW: i64 = 24 * 3600 * 1000;
+const BURST_MIN_DOWNVOTES: usize = 5;
+const NEW_ACCOUNT_WINDOW: i64 = 48 * 3600 * 1000;
+const NEW_ACCOUNT_MIN_VOTES: usize = 3;
+const REVERSAL_MIN_PAGES: usize = 2;
+const EVIDENCE_LIMIT: usize = 20;
+
+// A timestamped vote: when, which page by index, and the new value or voter
+type TimedVote<T> = (DateTime, usize, T);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind{
+    VotingCluster,
+    DownvoteBurst,
+    NewAccountVotes,
+    VoteReversals,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Finding{
+    pub kind: FindingKind,
+    pub score: f64,
+    pub users: Vec<i32>,
+    pub pages: Vec<String>,
+    pub start: Option<DateTime>,
+    pub end: Option<DateTime>,
+    pub evidence: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct MongoUserJoin{
+    id: i32,
+    join: DateTime,
+}
+
+fn time(date: DateTime) -> String{
+    date.try_to_rfc3339_string().unwrap_or_default()
+}
+
+// Votes already there at a page's first poll have no known time
+fn timed_changes<'a>(page: &MongoPage, changes: &'a [VoteChange]) -> impl Iterator<Item = &'a VoteChange>{
+    let first = page.rate_history.first().map(|entry| entry.timestamp);
+    changes.iter().filter(move |change| Some(change.timestamp) != first)
+}
+
+// Accounts whose votes match on nearly every page either of them voted on
+fn voting_clusters(pages: &[MongoPage], changes: &[Vec<VoteChange>]) -> Vec<Finding>{
+    let mut voters: HashMap<(usize, bool), Vec<i32>> = HashMap::new();
+    for (i, changes) in changes.iter().enumerate(){
+        for change in changes.iter().filter(|change| change.to != 0){
+            voters.entry((i, change.to > 0)).or_default().push(change.user_id);
+        }
+    }
+    voters.retain(|_, users| {
+        users.sort_unstable();
+        users.dedup();
+        users.len() <= CLUSTER_MAX_VOTERS
+    });
+
+    let mut ballots: HashMap<i32, HashSet<(usize, bool)>> = HashMap::new();
+    for (key, users) in &voters{
+        for user_id in users{
+            ballots.entry(*user_id).or_default().insert(*key);
+        }
+    }
+    ballots.retain(|_, ballot| ballot.len() >= CLUSTER_MIN_SHARED);
+
+    let mut shared: HashMap<(i32, i32), usize> = HashMap::new();
+    for users in voters.values_mut(){
+        users.retain(|user_id| ballots.contains_key(user_id));
+        for (i, a) in users.iter().enumerate(){
+            for b in &users[i + 1..]{
+                *shared.entry((*a, *b)).or_default() += 1;
+            }
+        }
+    }
+
+    let mut links: HashMap<i32, Vec<(i32, usize, f64)>> = HashMap::new();
+    for ((a, b), count) in shared{
+        let similarity = count as f64 / (ballots[&a].len() + ballots[&b].len() - count) as f64;
+        if count >= CLUSTER_MIN_SHARED && similarity >= CLUSTER_MIN_SIMILARITY {
+            links.entry(a).or_default().push((b, count, similarity));
+            links.entry(b).or_default().push((a, count, similarity));
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut findings = Vec::new();
+    let mut starts = links.keys().copied().collect::<Vec<_>>();
+    starts.sort_unstable();
+    for start in starts{
+        if !seen.insert(start) {
+            continue
+        }
+        let mut members = BTreeSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        let mut pairs = Vec::new();
+        while let Some(user_id) = queue.pop_front() {
+            for (other, count, similarity) in &links[&user_id]{
+                if user_id < *other {
+                    pairs.push((user_id, *other, *count, *similarity));
+                }
+                if seen.insert(*other) {
+                    members.insert(*other);
+                    queue.push_back(*other);
+                }
+            }
+        }
+        let common = members.iter()
+            .map(|user_id| &ballots[user_id])
+            .fold(None, |common: Option<HashSet<(usize, bool)>>, ballot| Some(match common {
+                Some(common) => common.intersection(ballot).copied().collect(),
+                None => ballot.clone(),
+            }))
+            .unwrap_or_default();
+        let mut common_pages = common.iter().map(|(i, _)| pages[*i].fullname.clone()).collect::<Vec<_>>();
+        common_pages.sort();
+        pairs.sort_by_key(|pair| std::cmp::Reverse(pair.2));
+        let average_shared = pairs.iter().map(|pair| pair.2 as f64).sum::<f64>() / pairs.len() as f64;
+        let average_similarity = pairs.iter().map(|pair| pair.3).sum::<f64>() / pairs.len() as f64;
+        findings.push(Finding{
+            kind: FindingKind::VotingCluster,
+            score: members.len() as f64 * average_shared * average_similarity,
+            users: members.into_iter().collect(),
+            pages: common_pages.into_iter().take(EVIDENCE_LIMIT).collect(),
+            start: None,
+            end: None,
+            evidence: pairs.iter()
+                .take(EVIDENCE_LIMIT)
+                .map(|(a, b, count, similarity)| format!("{a} and {b}: {count} identical votes, similarity {similarity:.2}"))
+                .collect(),
+        });
+    }
+    findings
+}
+
+// Many new downvotes on several pages of one author within a day
+fn downvote_bursts(pages: &[MongoPage], changes: &[Vec<VoteChange>]) -> Vec<Finding>{
+    let mut downvotes: HashMap<i32, Vec<TimedVote<i32>>> = HashMap::new();
+    for (i, (page, changes)) in pages.iter().zip(changes).enumerate(){
+        for change in timed_changes(page, changes).filter(|change| change.to < 0 && change.from >= 0){
+            for author in &page.author{
+                downvotes.entry(*author).or_default().push((change.timestamp, i, change.user_id));
+            }
+        }
+    }
+
+    let mut findings = Vec::new();
+    for (author, mut events) in downvotes{
+        events.sort_by_key(|event| (event.0, event.1, event.2));
+        let mut start = 0;
+        while start < events.len() {
+            let limit = events[start].0.timestamp_millis() + BURST_WINDOW;
+            let end = events[start..].iter().take_while(|event| event.0.timestamp_millis() <= limit).count() + start;
+            let window = &events[start..end];
+            let page_ids = window.iter().map(|event| event.1).collect::<BTreeSet<_>>();
+            if window.len() < BURST_MIN_DOWNVOTES || page_ids.len() < 2 {
+                start += 1;
+                continue
+            }
+            findings.push(Finding{
+                kind: FindingKind::DownvoteBurst,
+                score: window.len() as f64,
+                users: window.iter().map(|event| event.2).collect::<BTreeSet<_>>().into_iter().collect(),
+                pages: page_ids.iter().map(|i| pages[*i].fullname.clone()).collect(),
+                start: Some(window[0].0),
+                end: window.last().map(|event| event.0),
+                evidence: std::iter::once(format!("{} downvotes on {} pages by author {author}", window.len(), page_ids.len()))
+                    .chain(window.iter()
+                        .take(EVIDENCE_LIMIT)
+                        .map(|(timestamp, i, user_id)| format!("{user_id} downvoted {} by {}", pages[*i].fullname, time(*timestamp))))
+                    .collect(),
+            });
+            start = end;
+        }
+    }
+    findings
+}
+
+// Accounts that start voting within two days of joining
+fn new_account_votes(pages: &[MongoPage], changes: &[Vec<VoteChange>], joins: &HashMap<i32, DateTime>) -> Vec<Finding>{
+    let mut early: HashMap<i32, Vec<TimedVote<i8>>> = HashMap::new();
+    for (i, (page, changes)) in pages.iter().zip(changes).enumerate(){
+        for change in timed_changes(page, changes).filter(|change| change.to != 0 && change.from == 0){
+            let Some(join) = joins.get(&change.user_id) else { continue };
+            let since_join = change.timestamp.timestamp_millis() - join.timestamp_millis();
+            if (0..=NEW_ACCOUNT_WINDOW).contains(&since_join) {
+                early.entry(change.user_id).or_default().push((change.timestamp, i, change.to));
+            }
+        }
+    }
+
+    early.into_iter()
+        .filter(|(_, votes)| votes.len() >= NEW_ACCOUNT_MIN_VOTES)
+        .map(|(user_id, mut votes)| {
+            votes.sort_by_key(|vote| (vote.0, vote.1));
+            Finding{
+                kind: FindingKind::NewAccountVotes,
+                score: votes.len() as f64,
+                users: vec![user_id],
+                pages: votes.iter().map(|vote| pages[vote.1].fullname.clone()).collect::<BTreeSet<_>>().into_iter().collect(),
+                start: votes.first().map(|vote| vote.0),
+                end: votes.last().map(|vote| vote.0),
+                evidence: std::iter::once(format!("joined {}", time(joins[&user_id])))
+                    .chain(votes.iter()
+                        .take(EVIDENCE_LIMIT)
+                        .map(|(timestamp, i, value)| format!("{value:+} on {} by {}", pages[*i].fullname, time(*timestamp))))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+// Voters flipping their vote on several pages of the same author; the disputes themselves are not archived
+fn vote_reversals(pages: &[MongoPage], changes: &[Vec<VoteChange>]) -> Vec<Finding>{
+    let mut reversals: HashMap<(i32, i32), Vec<TimedVote<i8>>> = HashMap::new();
+    for (i, (page, changes)) in pages.iter().zip(changes).enumerate(){
+        let mut last_cast: HashMap<i32, i8> = HashMap::new();
+        for change in changes.iter().filter(|change| change.to != 0){
+            if last_cast.get(&change.user_id).is_some_and(|last| last.signum() != change.to.signum()) {
+                for author in &page.author{
+                    reversals.entry((change.user_id, *author)).or_default().push((change.timestamp, i, change.to));
+                }
+            }
+            last_cast.insert(change.user_id, change.to);
+        }
+    }
+
+    reversals.into_iter()
+        .filter_map(|((user_id, author), mut flips)| {
+            let page_ids = flips.iter().map(|flip| flip.1).collect::<BTreeSet<_>>();
+            if page_ids.len() < REVERSAL_MIN_PAGES {
+                return None
+            }
+            flips.sort_by_key(|flip| (flip.0, flip.1));
+            Some(Finding{
+                kind: FindingKind::VoteReversals,
+                score: 2.0 * flips.len() as f64,
+                users: vec![user_id],
+                pages: page_ids.iter().map(|i| pages[*i].fullname.clone()).collect(),
+                start: flips.first().map(|flip| flip.0),
+                end: flips.last().map(|flip| flip.0),
+                evidence: std::iter::once(format!("{} reversals on pages by author {author}", flips.len()))
+                    .chain(flips.iter()
+                        .take(EVIDENCE_LIMIT)
+                        .map(|(timestamp, i, value)| format!("changed to {value:+} on {} by {}", pages[*i].fullname, time(*timestamp))))
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+// Highest scores first; scores count the suspicious votes behind a finding, clusters weigh in their size
+pub fn analyze(pages: &[MongoPage], joins: &HashMap<i32, DateTime>) -> Vec<Finding>{
+    // Replaying a rate history is the costly part, so every detector shares one replay per page
+    let changes = pages.iter().map(MongoPage::vote_changes).collect::<Vec<_>>();
+    let mut findings = voting_clusters(pages, &changes);
+    findings.extend(downvote_bursts(pages, &changes));
+    findings.extend(new_account_votes(pages, &changes, joins));
+    findings.extend(vote_reversals(pages, &changes));
+    findings.sort_by(|a, b| b.score.total_cmp(&a.score)
+        .then(a.kind.cmp(&b.kind))
+        .then(a.users.cmp(&b.users))
+        .then(a.start.cmp(&b.start)));
+    findings
+}
+
+pub async fn vote_report(page_col: mongodb::Collection<MongoPage>, user_col: mongodb::Collection<MongoUser>) -> Result<Vec<Finding>, WikidotError>{
+    let pages = page_col.find(doc! {})
+        .projection(doc! {"source": 0, "history": 0})
+        .await?
+        .try_collect::<Vec<_>>().await?;
+    let joins = user_col.clone_with_type::<MongoUserJoin>()
+        .find(doc! {})
+        .projection(doc! {"id": 1, "join": 1})
+        .await?
+        .map_ok(|user| (user.id, user.join))
+        .try_collect::<HashMap<_, _>>().await?;
+    Ok(analyze(&pages, &joins))
+}
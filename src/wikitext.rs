@@ -0,0 +1,899 @@
+use std::{cell::RefCell, collections::HashMap, iter::Peekable};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// Tags whose content is parsed as blocks (paragraphs, lists, tables...) rather than inline text
+const BLOCK_TAGS: [&str; 20] = [
+    "div", "div_", "collapsible", "tabview", "tabs", "tab", "=", "<", ">", "==",
+    "table", "row", "cell", "hcell", "ul", "ol", "li", "note", "iftags", "gallery",
+];
+// Tags whose content is kept verbatim
+const RAW_TAGS: [&str; 6] = ["code", "html", "embed", "embedvideo", "embedaudio", "math"];
+// Tags that never take a closing tag
+const VOID_TAGS: [&str; 11] = [
+    "footnoteblock", "toc", "f<toc", "f>toc", "iframe", "button", "social", "date", "file", "newline", "bibcite",
+];
+const IMAGE_TAGS: [&str; 6] = ["image", "=image", "<image", ">image", "f<image", "f>image"];
+const FORMAT_DELIMS: [(&str, Style); 6] = [
+    ("**", Style::Bold),
+    ("//", Style::Italic),
+    ("__", Style::Underline),
+    ("--", Style::Strikethrough),
+    ("^^", Style::Superscript),
+    (",,", Style::Subscript),
+];
+const URL_PREFIXES: [&str; 4] = ["http://", "https://", "ftp://", "mailto:"];
+// Tags and formatting nested deeper than this are left empty, so hostile sources can't overflow the stack
+pub const MAX_NESTING: usize = 64;
+
+static ATTR_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"([\w:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"']+))"#).unwrap());
+static HEADING_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\+{1,6})(\*?) ").unwrap());
+static HR_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^-{4,}\s*$").unwrap());
+static LIST_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^( *)([*#]) ").unwrap());
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct Span{
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct Node{
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, Debug, PartialEq)]
+pub struct Attributes{
+    pub raw: String,
+    pub values: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum Style{
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Superscript,
+    Subscript,
+    Monospace,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum LinkKind{
+    Page,
+    Url,
+    Anchor,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum CellAlign{
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub enum NodeKind{
+    Text(String),
+    LineBreak,
+    Paragraph(Vec<Node>),
+    Heading{level: u8, toc: bool, children: Vec<Node>},
+    HorizontalRule,
+    List{ordered: bool, items: Vec<Node>},
+    ListItem(Vec<Node>),
+    Quote(Vec<Node>),
+    Table(Vec<Node>),
+    TableRow(Vec<Node>),
+    TableCell{header: bool, align: Option<CellAlign>, colspan: u16, children: Vec<Node>},
+    Format{style: Style, children: Vec<Node>},
+    Color{color: String, children: Vec<Node>},
+    Raw(String),
+    Comment(String),
+    Link{target: String, text: Option<String>, kind: LinkKind, new_window: bool},
+    Image{source: String, align: Option<String>, attributes: Attributes},
+    Anchor(String),
+    User{name: String, avatar: bool},
+    Math(String),
+    Include{target: String, variables: Vec<(String, String)>},
+    Module{name: String, attributes: Attributes, body: Option<String>},
+    RawBlock{name: String, attributes: Attributes, content: String},
+    Footnote(Vec<Node>),
+    Element{name: String, attributes: Attributes, children: Vec<Node>},
+}
+
+impl Span{
+    pub fn new(start: usize, end: usize) -> Self{
+        Span{start, end}
+    }
+}
+
+impl Node{
+    fn new(kind: NodeKind, start: usize, end: usize) -> Self{
+        Node{kind, span: Span::new(start, end)}
+    }
+
+    pub fn children(&self) -> &[Node]{
+        match &self.kind {
+            NodeKind::Paragraph(children)
+            | NodeKind::Heading{children, ..}
+            | NodeKind::List{items: children, ..}
+            | NodeKind::ListItem(children)
+            | NodeKind::Quote(children)
+            | NodeKind::Table(children)
+            | NodeKind::TableRow(children)
+            | NodeKind::TableCell{children, ..}
+            | NodeKind::Format{children, ..}
+            | NodeKind::Color{children, ..}
+            | NodeKind::Footnote(children)
+            | NodeKind::Element{children, ..} => children,
+            _ => &[],
+        }
+    }
+
+    // Depth-first, parents before children
+    pub fn walk<F: FnMut(&Node)>(&self, f: &mut F){
+        f(self);
+        for child in self.children(){
+            child.walk(f);
+        }
+    }
+}
+
+impl Attributes{
+    pub fn parse(raw: &str) -> Self{
+        let values = ATTR_RE.captures_iter(raw)
+            .map(|captures| {
+                let value = captures.get(2).or(captures.get(3)).or(captures.get(4))
+                    .map_or("", |m| m.as_str());
+                (captures[1].to_lowercase(), value.to_string())
+            })
+            .collect();
+        Attributes{raw: raw.trim().to_string(), values}
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str>{
+        self.values.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+pub fn walk<F: FnMut(&Node)>(nodes: &[Node], f: &mut F){
+    for node in nodes{
+        node.walk(f);
+    }
+}
+
+pub fn parse(source: &str) -> Vec<Node>{
+    let lower = source.to_ascii_lowercase();
+    let closings = closing_tags(&lower);
+    let closes = format_closes(source);
+    let mut parser = Parser{
+        src: source,
+        lower: &lower,
+        closings: &closings,
+        closes: &closes,
+        paragraph_ends: RefCell::default(),
+        pos: 0,
+        limit: source.len(),
+        open: Vec::new(),
+        depth: 0,
+    };
+    parser.parse_blocks()
+}
+
+// Every place a formatting delimiter can close, that is, right after a non-space character
+fn format_closes(source: &str) -> HashMap<&'static str, Vec<usize>>{
+    let bytes = source.as_bytes();
+    ["}}", "##"].into_iter()
+        .chain(FORMAT_DELIMS.iter().map(|(delim, _)| *delim))
+        .map(|delim| {
+            let closes = (1..bytes.len())
+                .filter(|i| bytes[*i..].starts_with(delim.as_bytes()) && !source[..*i].ends_with(char::is_whitespace))
+                .collect();
+            (delim, closes)
+        })
+        .collect()
+}
+
+// Start and end of every closing tag by name, found in one pass so unclosed tags don't rescan the page
+fn closing_tags(lower: &str) -> HashMap<String, Vec<(usize, usize)>>{
+    let bytes = lower.as_bytes();
+    let brackets = (0..bytes.len().saturating_sub(1))
+        .filter(|i| bytes[*i] == b']' && bytes[i + 1] == b']')
+        .collect::<Vec<_>>();
+    let mut closings: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+    for (start, _) in lower.match_indices("[[/"){
+        let Some(end) = brackets.get(brackets.partition_point(|i| *i < start + 3)) else { break };
+        let name = lower[start + 3..*end].trim();
+        if !name.is_empty() && !name.contains('\n') {
+            closings.entry(name.to_string()).or_default().push((start, end + 2));
+        }
+    }
+    closings
+}
+
+#[derive(Clone, Copy, Default)]
+struct Stop{
+    line: bool,
+    cell: bool,
+}
+
+struct Parser<'a>{
+    src: &'a str,
+    lower: &'a str,
+    closings: &'a HashMap<String, Vec<(usize, usize)>>,
+    closes: &'a HashMap<&'static str, Vec<usize>>,
+    // Paragraph end by line end and limit, only valid for the current open tags
+    paragraph_ends: RefCell<HashMap<(usize, usize), usize>>,
+    pos: usize,
+    // Parsing never looks past this offset; narrowed while inside formatting
+    limit: usize,
+    open: Vec<String>,
+    depth: usize,
+}
+
+impl<'a> Parser<'a>{
+    fn text(&self) -> &'a str{
+        &self.src[..self.limit]
+    }
+
+    fn lower_text(&self) -> &'a str{
+        &self.lower[..self.limit]
+    }
+
+    fn rest(&self) -> &'a str{
+        &self.text()[self.pos..]
+    }
+
+    fn at_line_start(&self) -> bool{
+        self.pos == 0 || self.text()[..self.pos].ends_with('\n')
+    }
+
+    fn line_end(&self, from: usize) -> usize{
+        self.text()[from..].find('\n').map_or(self.text().len(), |i| from + i)
+    }
+
+    fn next_char_len(&self) -> usize{
+        self.rest().chars().next().map_or(1, |c| c.len_utf8())
+    }
+
+    fn find_lower(&self, from: usize, needle: &str) -> Option<usize>{
+        self.lower_text()[from..].find(needle).map(|i| from + i)
+    }
+
+    // Name of the closing tag at the cursor, if it closes something currently open
+    fn closing_tag(&self) -> Option<String>{
+        let name = self.closing_tag_at(self.pos)?;
+        self.open.contains(&name).then_some(name)
+    }
+
+    fn closing_tag_at(&self, at: usize) -> Option<String>{
+        let rest = &self.lower_text()[at..];
+        let inner = rest.strip_prefix("[[/")?;
+        let end = inner.find("]]")?;
+        let name = inner[..end].trim();
+        (!name.is_empty() && !name.contains('\n')).then(|| name.to_string())
+    }
+
+    // Runs `parse` inside the tag, whose closing tag now ends paragraphs
+    fn parse_open<T>(&mut self, name: &str, parse: impl FnOnce(&mut Self) -> T) -> T{
+        self.open.push(name.to_string());
+        self.paragraph_ends.borrow_mut().clear();
+        self.depth += 1;
+        let parsed = parse(self);
+        self.depth -= 1;
+        self.open.pop();
+        self.paragraph_ends.borrow_mut().clear();
+        parsed
+    }
+
+    fn consume_closing(&mut self, name: &str) -> bool{
+        if self.closing_tag_at(self.pos).as_deref() == Some(name) {
+            self.pos += self.rest().find("]]").map_or(0, |i| i + 2);
+            true
+        }
+        else {false}
+    }
+
+    fn tag_name_at(&self, at: usize) -> Option<String>{
+        let inner = self.lower_text()[at..].strip_prefix("[[")?;
+        let end = inner.find(|c: char| c.is_whitespace() || c == ']').unwrap_or(inner.len());
+        Some(inner[..end].to_string())
+    }
+
+    fn starts_block(&self, at: usize) -> bool{
+        let line = &self.text()[at..self.line_end(at)];
+        if line.trim().is_empty()
+            || HEADING_RE.is_match(line)
+            || HR_RE.is_match(line)
+            || LIST_RE.is_match(line)
+            || line.starts_with("||")
+            || line.starts_with('>')
+        {
+            return true
+        }
+        if let Some(name) = self.closing_tag_at(at) {
+            return self.open.contains(&name)
+        }
+        match self.tag_name_at(at) {
+            Some(name) => BLOCK_TAGS.contains(&name.as_str())
+                || RAW_TAGS.contains(&name.as_str())
+                || ["module", "include", "footnoteblock", "toc", "f<toc", "f>toc"].contains(&name.as_str()),
+            None => false,
+        }
+    }
+
+    fn parse_blocks(&mut self) -> Vec<Node>{
+        let mut nodes = Vec::new();
+        while self.pos < self.text().len() {
+            if self.closing_tag().is_some() {
+                break;
+            }
+            let line_end = self.line_end(self.pos);
+            if self.text()[self.pos..line_end].trim().is_empty() {
+                self.pos = (line_end + 1).min(self.text().len());
+                continue;
+            }
+
+            let node = if self.at_line_start() {self.parse_line_block()} else {None};
+            match node {
+                Some(node) => nodes.push(node),
+                None => {
+                    let before = self.pos;
+                    if let Some(node) = self.parse_paragraph() {
+                        nodes.push(node);
+                    }
+                    if self.pos == before {
+                        // Nothing could be parsed here; keep the rest of the line as text
+                        let end = self.line_end(self.pos);
+                        nodes.push(Node::new(NodeKind::Text(self.text()[self.pos..end].to_string()), self.pos, end));
+                        self.pos = end;
+                    }
+                },
+            }
+        }
+        nodes
+    }
+
+    fn parse_line_block(&mut self) -> Option<Node>{
+        let start = self.pos;
+        let line_end = self.line_end(start);
+        let line = &self.text()[start..line_end];
+
+        if let Some(captures) = HEADING_RE.captures(line) {
+            let level = captures[1].len() as u8;
+            let toc = captures[2].is_empty();
+            self.pos = start + captures[0].len();
+            let children = self.parse_inline(Stop{line: true, ..Stop::default()});
+            return Some(Node::new(NodeKind::Heading{level, toc, children}, start, self.pos))
+        }
+        if HR_RE.is_match(line) {
+            self.pos = line_end;
+            return Some(Node::new(NodeKind::HorizontalRule, start, line_end))
+        }
+        if LIST_RE.is_match(line) {
+            return Some(self.parse_list())
+        }
+        if line.starts_with("||") {
+            return Some(self.parse_table())
+        }
+        if line.starts_with('>') {
+            return Some(self.parse_quote())
+        }
+        if line.starts_with("[[") && self.starts_block(start) {
+            return self.parse_tag()
+        }
+        None
+    }
+
+    fn parse_paragraph(&mut self) -> Option<Node>{
+        let start = self.pos;
+        let children = self.parse_inline(Stop::default());
+        (!children.is_empty()).then(|| Node::new(NodeKind::Paragraph(children), start, self.pos))
+    }
+
+    fn parse_list(&mut self) -> Node{
+        let mut items = Vec::new();
+        while self.pos < self.text().len() {
+            let start = self.pos;
+            let line_end = self.line_end(start);
+            let Some(captures) = LIST_RE.captures(&self.text()[start..line_end]) else { break };
+            let depth = captures[1].len();
+            let ordered = &captures[2] == "#";
+            // A shallower item or a change of list type at the top level starts a new list
+            if let Some(&(first_depth, first_ordered, _)) = items.first() {
+                if depth < first_depth || (depth == first_depth && ordered != first_ordered) {
+                    break;
+                }
+            }
+            self.pos = start + captures[0].len();
+            let children = self.parse_inline(Stop{line: true, ..Stop::default()});
+            items.push((depth, ordered, Node::new(NodeKind::ListItem(children), start, self.pos)));
+            if self.rest().starts_with('\n') {
+                self.pos += 1;
+            }
+        }
+        let depth = items.first().map_or(0, |item| item.0);
+        build_list(&mut items.into_iter().peekable(), depth)
+    }
+
+    fn parse_table(&mut self) -> Node{
+        let start = self.pos;
+        let mut rows = Vec::new();
+        while self.rest().starts_with("||") {
+            let row_start = self.pos;
+            let mut cells = Vec::new();
+            loop {
+                let cell_start = self.pos;
+                let mut colspan: u16 = 0;
+                while self.rest().starts_with("||") {
+                    self.pos += 2;
+                    colspan += 1;
+                }
+                let after = self.rest().trim_start_matches([' ', '\t']);
+                if after.is_empty() || after.starts_with('\n') {
+                    self.pos = self.line_end(self.pos);
+                    break;
+                }
+                let (header, align) = match self.rest().chars().next() {
+                    Some('~') => (true, None),
+                    Some('<') => (false, Some(CellAlign::Left)),
+                    Some('>') => (false, Some(CellAlign::Right)),
+                    Some('=') => (false, Some(CellAlign::Center)),
+                    _ => (false, None),
+                };
+                if header || align.is_some() {
+                    self.pos += 1;
+                }
+                let children = self.parse_inline(Stop{line: true, cell: true});
+                cells.push(Node::new(NodeKind::TableCell{header, align, colspan: colspan.max(1), children}, cell_start, self.pos));
+                if !self.rest().starts_with("||") {
+                    self.pos = self.line_end(self.pos);
+                    break;
+                }
+            }
+            rows.push(Node::new(NodeKind::TableRow(cells), row_start, self.pos));
+            if self.rest().starts_with('\n') && self.text()[self.pos + 1..].starts_with("||") {
+                self.pos += 1;
+            }
+            else {break}
+        }
+        Node::new(NodeKind::Table(rows), start, self.pos)
+    }
+
+    fn parse_quote(&mut self) -> Node{
+        let mut lines = Vec::new();
+        while self.rest().starts_with('>') {
+            let start = self.pos;
+            let depth = self.rest().len() - self.rest().trim_start_matches('>').len();
+            self.pos += depth;
+            if self.rest().starts_with(' ') {
+                self.pos += 1;
+            }
+            let children = self.parse_inline(Stop{line: true, ..Stop::default()});
+            lines.push((depth, start, self.pos, children));
+            if self.rest().starts_with('\n') && self.text()[self.pos + 1..].starts_with('>') {
+                self.pos += 1;
+            }
+            else {break}
+        }
+        build_quote(&mut lines.into_iter().peekable(), 1)
+    }
+
+    fn parse_inline(&mut self, stop: Stop) -> Vec<Node>{
+        let mut nodes = Vec::new();
+        let mut text_start = self.pos;
+
+        macro_rules! flush {
+            () => {
+                if text_start < self.pos {
+                    nodes.push(Node::new(NodeKind::Text(self.text()[text_start..self.pos].to_string()), text_start, self.pos));
+                }
+            };
+        }
+
+        while self.pos < self.text().len() {
+            let rest = self.rest();
+            if self.closing_tag().is_some() {
+                break;
+            }
+            if stop.cell && rest.starts_with("||") {
+                break;
+            }
+            if rest.starts_with('\n') {
+                if stop.line || self.pos + 1 >= self.text().len() || self.starts_block(self.pos + 1) {
+                    break;
+                }
+                flush!();
+                nodes.push(Node::new(NodeKind::LineBreak, self.pos, self.pos + 1));
+                self.pos += 1;
+                text_start = self.pos;
+                continue;
+            }
+
+            let start = self.pos;
+            let node = if rest.starts_with("[!--") {
+                self.parse_comment()
+            }
+            else if rest.starts_with("[[[") {
+                self.parse_triple_link()
+            }
+            else if rest.starts_with("[[") {
+                self.parse_tag()
+            }
+            else if rest.starts_with('[') {
+                self.parse_single_link()
+            }
+            else if rest.starts_with("@@") {
+                self.parse_raw("@@", "@@")
+            }
+            else if rest.starts_with("@<") {
+                self.parse_raw("@<", ">@")
+            }
+            else if rest.starts_with("{{") {
+                self.parse_format("{{", "}}", Style::Monospace, stop)
+            }
+            else if rest.starts_with("##") {
+                self.parse_color(stop)
+            }
+            else if let Some(&(delim, style)) = FORMAT_DELIMS.iter().find(|(delim, _)| rest.starts_with(delim)) {
+                self.parse_format(delim, delim, style, stop)
+            }
+            else if URL_PREFIXES.iter().any(|prefix| rest.starts_with(prefix) || rest.strip_prefix('*').is_some_and(|r| r.starts_with(prefix)))
+                && !self.text()[..self.pos].ends_with(|c: char| c.is_alphanumeric())
+            {
+                self.parse_bare_url()
+            }
+            else {None};
+
+            match node {
+                Some(node) => {
+                    let end = self.pos;
+                    self.pos = start;
+                    flush!();
+                    self.pos = end;
+                    nodes.push(node);
+                    text_start = self.pos;
+                },
+                None => {
+                    self.pos = start + self.next_char_len();
+                    // Multi-char markup that failed to parse is kept as text as a whole
+                    for prefix in ["[[[", "[[", "@@", "##", "{{", "**", "//", "__", "--", "^^", ",,"] {
+                        if rest.starts_with(prefix) {
+                            self.pos = start + prefix.len();
+                            break;
+                        }
+                    }
+                },
+            }
+        }
+        flush!();
+        nodes
+    }
+
+    // Cached per line, so the lines of a long paragraph are only checked once
+    fn paragraph_end(&self, from: usize, stop: Stop) -> usize{
+        if stop.line {
+            return self.line_end(from)
+        }
+        let mut lines = Vec::new();
+        let mut at = from;
+        let paragraph_end = loop {
+            let end = self.line_end(at);
+            if let Some(&cached) = self.paragraph_ends.borrow().get(&(end, self.limit)) {
+                break cached
+            }
+            lines.push(end);
+            if end >= self.text().len() || self.starts_block(end + 1) {
+                break end
+            }
+            at = end + 1;
+        };
+        let mut paragraph_ends = self.paragraph_ends.borrow_mut();
+        for end in lines{
+            paragraph_ends.insert((end, self.limit), paragraph_end);
+        }
+        paragraph_end
+    }
+
+    // Like Wikidot, formatting closes at the first matching delimiter that follows a non-space character
+    fn find_close(&self, from: usize, close: &str, stop: Stop) -> Option<usize>{
+        let closes = self.closes.get(close)?;
+        let found = *closes.get(closes.partition_point(|at| *at <= from))?;
+        (found + close.len() <= self.paragraph_end(from, stop)).then_some(found)
+    }
+
+    fn parse_limited(&mut self, from: usize, to: usize, stop: Stop) -> Option<Vec<Node>>{
+        if self.depth >= MAX_NESTING {
+            return None
+        }
+        let (saved_pos, saved_limit) = (self.pos, self.limit);
+        self.pos = from;
+        self.limit = to;
+        self.depth += 1;
+        let children = self.parse_inline(stop);
+        self.depth -= 1;
+        let reached = self.pos == to;
+        self.limit = saved_limit;
+        if !reached {
+            self.pos = saved_pos;
+            return None
+        }
+        Some(children)
+    }
+
+    fn parse_format(&mut self, open: &'static str, close: &'static str, style: Style, stop: Stop) -> Option<Node>{
+        let start = self.pos;
+        let inner_start = start + open.len();
+        let next = self.text()[inner_start..].chars().next()?;
+        if next.is_whitespace() {
+            return None
+        }
+        let close_at = self.find_close(inner_start, close, stop)?;
+        let children = self.parse_limited(inner_start, close_at, stop)?;
+        self.pos = close_at + close.len();
+        Some(Node::new(NodeKind::Format{style, children}, start, self.pos))
+    }
+
+    fn parse_color(&mut self, stop: Stop) -> Option<Node>{
+        let start = self.pos;
+        let inner_start = start + 2;
+        let bar = self.text()[inner_start..self.line_end(inner_start)].find('|')? + inner_start;
+        let color = self.text()[inner_start..bar].trim();
+        if color.is_empty() || !color.chars().all(|c| c.is_ascii_alphanumeric() || c == '#') {
+            return None
+        }
+        let close_at = self.find_close(bar + 1, "##", stop)?;
+        let children = self.parse_limited(bar + 1, close_at, stop)?;
+        self.pos = close_at + 2;
+        Some(Node::new(NodeKind::Color{color: color.to_string(), children}, start, self.pos))
+    }
+
+    fn parse_raw(&mut self, open: &str, close: &str) -> Option<Node>{
+        let start = self.pos;
+        let inner_start = start + open.len();
+        let end = self.text()[inner_start..self.line_end(inner_start)].find(close)? + inner_start;
+        self.pos = end + close.len();
+        Some(Node::new(NodeKind::Raw(self.text()[inner_start..end].to_string()), start, self.pos))
+    }
+
+    fn parse_comment(&mut self) -> Option<Node>{
+        let start = self.pos;
+        let end = self.text()[start + 4..].find("--]")? + start + 4;
+        self.pos = end + 3;
+        Some(Node::new(NodeKind::Comment(self.text()[start + 4..end].to_string()), start, self.pos))
+    }
+
+    fn parse_triple_link(&mut self) -> Option<Node>{
+        let start = self.pos;
+        let inner_start = start + 3;
+        let end = self.text()[inner_start..self.line_end(inner_start)].find("]]]")? + inner_start;
+        let inner = &self.text()[inner_start..end];
+        let (target, text) = match inner.split_once('|') {
+            Some((target, text)) => (target, Some(text.trim()).filter(|text| !text.is_empty())),
+            None => (inner, None),
+        };
+        let target = target.trim();
+        let new_window = target.starts_with('*');
+        let target = target.trim_start_matches('*').trim();
+        if target.is_empty() {
+            return None
+        }
+        let kind = if URL_PREFIXES.iter().any(|prefix| target.starts_with(prefix)) {LinkKind::Url}
+            else if target.starts_with('#') {LinkKind::Anchor}
+            else {LinkKind::Page};
+        self.pos = end + 3;
+        Some(Node::new(NodeKind::Link{
+            target: target.to_string(),
+            text: text.map(|text| text.to_string()),
+            kind,
+            new_window,
+        }, start, self.pos))
+    }
+
+    fn parse_single_link(&mut self) -> Option<Node>{
+        let start = self.pos;
+        let inner_start = start + 1;
+        let end = self.text()[inner_start..self.line_end(inner_start)].find(']')? + inner_start;
+        let inner = &self.text()[inner_start..end];
+        let new_window = inner.starts_with('*');
+        let inner = inner.trim_start_matches('*');
+        let (target, text) = match inner.split_once(char::is_whitespace) {
+            Some((target, text)) => (target, Some(text.trim()).filter(|text| !text.is_empty())),
+            None => (inner, None),
+        };
+        let kind = if URL_PREFIXES.iter().any(|prefix| target.starts_with(prefix)) {LinkKind::Url}
+            else if target.starts_with('/') && target.len() > 1 {LinkKind::Page}
+            else if target.starts_with('#') {LinkKind::Anchor}
+            else {return None};
+        self.pos = end + 1;
+        Some(Node::new(NodeKind::Link{
+            target: target.to_string(),
+            text: text.map(|text| text.to_string()),
+            kind,
+            new_window,
+        }, start, self.pos))
+    }
+
+    fn parse_bare_url(&mut self) -> Option<Node>{
+        let start = self.pos;
+        let new_window = self.rest().starts_with('*');
+        let url_start = if new_window {start + 1} else {start};
+        let len = self.text()[url_start..]
+            .find(|c: char| c.is_whitespace() || ['[', ']', '|', '"', '<', '>'].contains(&c))
+            .unwrap_or(self.text().len() - url_start);
+        let url = self.text()[url_start..url_start + len].trim_end_matches(['.', ',', ')', ';', ':', '!', '?']);
+        if URL_PREFIXES.contains(&url) {
+            return None
+        }
+        self.pos = url_start + url.len();
+        Some(Node::new(NodeKind::Link{target: url.to_string(), text: None, kind: LinkKind::Url, new_window}, start, self.pos))
+    }
+
+    fn parse_tag(&mut self) -> Option<Node>{
+        let start = self.pos;
+        let rest = self.rest();
+        if rest.starts_with("[[/") {
+            return None
+        }
+        if rest.starts_with("[[$") {
+            let end = self.text()[start + 3..].find("$]]")? + start + 3;
+            self.pos = end + 3;
+            return Some(Node::new(NodeKind::Math(self.text()[start + 3..end].trim().to_string()), start, self.pos))
+        }
+
+        let header_end = self.text()[start + 2..].find("]]")? + start + 2;
+        let header = &self.text()[start + 2..header_end];
+        let name_len = header.find(char::is_whitespace).unwrap_or(header.len());
+        let name = header[..name_len].to_ascii_lowercase();
+        let args = &header[name_len..];
+        if name.is_empty() || (header.contains('\n') && name != "include" && name != "module") {
+            return None
+        }
+        let after = header_end + 2;
+        self.pos = after;
+
+        let kind = match name.as_str() {
+            "include" => {
+                let mut segments = args.split('|');
+                let first = segments.next().unwrap_or("").trim();
+                let (target, first_var) = match first.split_once(char::is_whitespace) {
+                    Some((target, var)) => (target, Some(var)),
+                    None => (first, None),
+                };
+                let variables = first_var.into_iter().chain(segments)
+                    .filter_map(|var| var.split_once('='))
+                    .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                    .filter(|(key, _)| !key.is_empty())
+                    .collect();
+                NodeKind::Include{target: target.to_string(), variables}
+            },
+            "module" => {
+                let args = args.trim();
+                let module_len = args.find(char::is_whitespace).unwrap_or(args.len());
+                let close = self.find_lower(after, "[[/module]]");
+                let next_open = self.find_lower(after, "[[module ").or(self.find_lower(after, "[[module]]"));
+                let body = match (close, next_open) {
+                    (Some(close), Some(next)) if close > next => None,
+                    (Some(close), _) => {
+                        self.pos = close + "[[/module]]".len();
+                        Some(self.text()[after..close].to_string())
+                    },
+                    (None, _) => None,
+                };
+                NodeKind::Module{
+                    name: args[..module_len].to_string(),
+                    attributes: Attributes::parse(&args[module_len..]),
+                    body,
+                }
+            },
+            "#" => NodeKind::Anchor(args.trim().to_string()),
+            "user" | "*user" => NodeKind::User{name: args.trim().to_string(), avatar: name == "*user"},
+            "footnote" if self.depth < MAX_NESTING => {
+                let children = self.parse_open(&name, |parser| parser.parse_inline(Stop::default()));
+                self.consume_closing(&name);
+                NodeKind::Footnote(children)
+            },
+            name if IMAGE_TAGS.contains(&name) => {
+                let args = args.trim();
+                let source_len = args.find(char::is_whitespace).unwrap_or(args.len());
+                let align = name.strip_suffix("image").filter(|align| !align.is_empty()).map(|align| align.to_string());
+                NodeKind::Image{
+                    source: args[..source_len].to_string(),
+                    align,
+                    attributes: Attributes::parse(&args[source_len..]),
+                }
+            },
+            name if RAW_TAGS.contains(&name) => {
+                let closing = format!("[[/{name}]]");
+                let content = match self.find_lower(after, &closing) {
+                    Some(close) => {
+                        self.pos = close + closing.len();
+                        self.text()[after..close].trim_start_matches('\n').to_string()
+                    },
+                    None => String::new(),
+                };
+                NodeKind::RawBlock{name: name.to_string(), attributes: Attributes::parse(args), content}
+            },
+            name if VOID_TAGS.contains(&name) || self.depth >= MAX_NESTING || !self.has_closing(after, name) => {
+                NodeKind::Element{name: name.to_string(), attributes: Attributes::parse(args), children: Vec::new()}
+            },
+            name => {
+                let children = self.parse_open(name, |parser| if BLOCK_TAGS.contains(&name) {
+                    parser.parse_blocks()
+                }
+                else {
+                    parser.parse_inline(Stop::default())
+                });
+                self.consume_closing(name);
+                NodeKind::Element{name: name.to_string(), attributes: Attributes::parse(args), children}
+            },
+        };
+
+        Some(Node::new(kind, start, self.pos))
+    }
+
+    fn has_closing(&self, from: usize, name: &str) -> bool{
+        let Some(closings) = self.closings.get(name) else { return false };
+        closings.get(closings.partition_point(|(start, _)| *start < from))
+            .is_some_and(|(_, end)| *end <= self.limit)
+    }
+}
+
+fn build_list<I: Iterator<Item = (usize, bool, Node)>>(items: &mut Peekable<I>, depth: usize) -> Node{
+    let ordered = items.peek().is_some_and(|item| item.1);
+    let mut list_items: Vec<Node> = Vec::new();
+    while let Some(&(item_depth, item_ordered, _)) = items.peek() {
+        if item_depth < depth || (item_depth == depth && item_ordered != ordered && !list_items.is_empty()) {
+            break;
+        }
+        if item_depth > depth {
+            let nested = build_list(items, item_depth);
+            match list_items.last_mut() {
+                Some(Node{kind: NodeKind::ListItem(children), span}) => {
+                    span.end = nested.span.end;
+                    children.push(nested);
+                },
+                _ => {
+                    let span = nested.span;
+                    list_items.push(Node{kind: NodeKind::ListItem(vec![nested]), span});
+                },
+            }
+            continue;
+        }
+        if let Some((_, _, item)) = items.next() {
+            list_items.push(item);
+        }
+    }
+    let start = list_items.first().map_or(0, |item| item.span.start);
+    let end = list_items.last().map_or(0, |item| item.span.end);
+    Node::new(NodeKind::List{ordered, items: list_items}, start, end)
+}
+
+fn build_quote<I: Iterator<Item = (usize, usize, usize, Vec<Node>)>>(lines: &mut Peekable<I>, depth: usize) -> Node{
+    let mut children: Vec<Node> = Vec::new();
+    let mut start = None;
+    let mut end = 0;
+    while let Some(&(line_depth, line_start, _, _)) = lines.peek() {
+        if line_depth < depth {
+            break;
+        }
+        start.get_or_insert(line_start);
+        if !children.is_empty() {
+            children.push(Node::new(NodeKind::LineBreak, end, end + 1));
+        }
+        if line_depth > depth {
+            let nested = build_quote(lines, depth + 1);
+            end = nested.span.end;
+            children.push(nested);
+            continue;
+        }
+        if let Some((_, _, line_end, line_children)) = lines.next() {
+            end = line_end;
+            children.extend(line_children);
+        }
+    }
+    Node::new(NodeKind::Quote(children), start.unwrap_or(end), end)
+}
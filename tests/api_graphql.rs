@@ -0,0 +1,26 @@
+use wikidot::{api_graphql::schema, api_rest::ApiState};
+
+// Complexity is checked before anything is read, so the database is never reached
+async fn errors(query: &str) -> Vec<String>{
+    let mongo = mongodb::Client::with_uri_str("mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=100").await.unwrap();
+    let schema = schema(&ApiState::new(&mongo.database("backrooms-wiki-cn"), "backrooms-wiki-cn"));
+    schema.execute(query).await.errors.into_iter().map(|e| e.message).collect()
+}
+
+#[tokio::test]
+async fn small_page_list_is_allowed(){
+    let errors = errors("{ pages(filter: {perPage: 20}) { title votes { value user { title } } } }").await;
+    assert!(!errors.iter().any(|e| e.contains("too complex")), "{errors:?}");
+}
+
+#[tokio::test]
+async fn full_page_list_with_voters_is_refused(){
+    let errors = errors("{ pages(filter: {perPage: 200}) { title votes { value user { title } } } }").await;
+    assert!(errors.iter().any(|e| e.contains("too complex")), "{errors:?}");
+}
+
+#[tokio::test]
+async fn nested_vote_lists_are_refused(){
+    let errors = errors("{ user(id: 1) { votesCast { page { votes { user { title } } } } } }").await;
+    assert!(errors.iter().any(|e| e.contains("too complex")), "{errors:?}");
+}
@@ -0,0 +1,29 @@
+use reqwest::StatusCode;
+use tokio::net::TcpListener;
+use wikidot::api_rest::{router, ApiState};
+
+// Serves the api over a database that can't be reached, so only routing and query parsing succeed
+async fn serve() -> String{
+    let mongo = mongodb::Client::with_uri_str("mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=100").await.unwrap();
+    let state = ApiState::new(&mongo.database("backrooms-wiki-cn"), "backrooms-wiki-cn");
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router(state)).await.unwrap() });
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn page_html_route_reaches_the_archive(){
+    let base = serve().await;
+    let response = reqwest::get(format!("{base}/pages/name/level-0/html?revision=3")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let response = reqwest::get(format!("{base}/pages/name/level-0/html")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn page_html_revision_must_be_an_index(){
+    let base = serve().await;
+    let response = reqwest::get(format!("{base}/pages/name/level-0/html?revision=latest")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
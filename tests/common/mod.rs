@@ -0,0 +1,38 @@
+// Each test crate only uses some of these
+#![allow(dead_code)]
+
+use mongodb::bson::DateTime;
+use wikidot::mongo_page::{MongoPage, MongoTextStats};
+
+pub fn at(hours: i64) -> DateTime{
+    DateTime::from_millis(hours * 3600 * 1000)
+}
+
+pub fn page(id: i32, author: i32) -> MongoPage{
+    MongoPage{
+        id,
+        author: vec![author],
+        fullname: format!("page-{id}"),
+        title: format!("Page {id}"),
+        tags: Vec::new(),
+        source: String::new(),
+        rate_history: Vec::new(),
+        votes_polled: None,
+        created_at: None,
+        updated_at: None,
+        last_change: None,
+        history: Vec::new(),
+        comments_count: 0,
+        status: true,
+        alternative: String::new(),
+        text_stats: Some(MongoTextStats::default()),
+    }
+}
+
+// Records one poll the way update_page does
+pub fn record_poll(page: &mut MongoPage, hour: i64, poll: impl IntoIterator<Item = (i32, i8)>){
+    let stored = poll.into_iter().map(|(user_id, vote)| (user_id.to_string(), vote)).collect::<std::collections::HashMap<_, _>>();
+    let up = stored.values().filter(|vote| **vote > 0).count() as i16;
+    let down = stored.values().filter(|vote| **vote < 0).count() as i16;
+    page.record_votes(stored, up, down, at(hour));
+}
@@ -0,0 +1,47 @@
+use wikidot::config::{Config, SiteConfig};
+
+fn config() -> Config{
+    Config{
+        sites: vec![
+            SiteConfig{name: String::from("backrooms-wiki-cn"), ..SiteConfig::default()},
+            SiteConfig{name: String::from("scp-wiki-cn"), database: String::from("scp-cn"), ..SiteConfig::default()},
+        ],
+        ..Config::default()
+    }
+}
+
+#[test]
+fn db_override_needs_a_site(){
+    assert!(config().select_sites(None, Some("archive")).is_err());
+
+    let sites = config().select_sites(Some("scp-wiki-cn"), Some("archive")).unwrap();
+    assert_eq!(sites.len(), 1);
+    assert_eq!(sites[0].database, "archive");
+}
+
+#[test]
+fn every_site_keeps_its_database(){
+    let databases = config().select_sites(None, None).unwrap()
+        .into_iter()
+        .map(|site| site.database)
+        .collect::<Vec<_>>();
+    assert_eq!(databases, vec!["backrooms-wiki-cn", "scp-cn"]);
+}
+
+#[test]
+fn unlisted_site_gets_its_own_database(){
+    let site = config().select_sites(Some("wanderers-library"), None).unwrap().remove(0);
+    assert_eq!(site.database, "wanderers-library");
+    assert_eq!(site.attribution_url, "https://wanderers-library.wikidot.com/attribution-metadata");
+
+    // Without a site list, `site` is still crawled into `database.name`
+    let legacy = Config::default();
+    let site = legacy.select_sites(Some(&legacy.site), None).unwrap().remove(0);
+    assert_eq!(site.database, legacy.database.name);
+}
+
+#[test]
+fn site_name_is_checked(){
+    assert!(config().select_sites(Some("Backrooms Wiki"), None).is_err());
+    assert!(config().select_sites(Some("../other"), None).is_err());
+}
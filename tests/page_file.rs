@@ -0,0 +1,36 @@
+use wikidot::page_file::{parse_files, upload_status};
+
+#[test]
+fn file_rows_become_files(){
+    let body = r#"<table class="page-files">
+        <tr id="file-row-101"><td><a href="http://scp-wiki-cn.wdfiles.com/local--files/scp-001/image.png">image.png</a></td><td>12 kB</td></tr>
+        <tr id="file-row-102"><td><a href="http://scp-wiki-cn.wdfiles.com/local--files/scp-001/notes.txt"> notes.txt </a></td><td>1 kB</td></tr>
+    </table>"#;
+    let files = parse_files(body).unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0].id, 101);
+    assert_eq!(files[0].name, "image.png");
+    assert_eq!(files[0].url, "http://scp-wiki-cn.wdfiles.com/local--files/scp-001/image.png");
+    assert_eq!(files[1].id, 102);
+    assert_eq!(files[1].name, "notes.txt");
+}
+
+#[test]
+fn page_without_files_is_empty(){
+    assert!(parse_files("<p>No files attached to this page.</p>").unwrap().is_empty());
+}
+
+#[test]
+fn file_row_without_link_fails(){
+    assert!(parse_files(r#"<table><tr id="file-row-7"><td>broken</td></tr></table>"#).is_err());
+}
+
+#[test]
+fn upload_status_is_read_from_the_target_page(){
+    assert!(upload_status(r#"<script>window.parent.WIKIDOT.modules.PageUploadModule.listeners.fileUploaded({"status":"ok","message":""});</script>"#).is_ok());
+
+    let error = upload_status(r#"<script>fileUploaded({"status":"not_ok","message":"File exceeds the size limit"});</script>"#).unwrap_err();
+    assert!(error.to_string().contains("File exceeds the size limit"));
+
+    assert!(upload_status("<html>Service unavailable</html>").is_err());
+}
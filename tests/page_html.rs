@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use wikidot::page_html::{render_html, HtmlOptions};
+
+fn render(source: &str) -> String{
+    let includes = HashMap::new();
+    render_html(source, &HtmlOptions{fullname: "level-0", page_base: "/", page_suffix: "", file_base: "/local--files/", includes: &includes})
+}
+
+#[test]
+fn script_links_are_dropped(){
+    for source in [
+        "[[a href=\"javascript:alert(1)\"]]x[[/a]]",
+        "[[a href=\" JavaScript:alert(1)\"]]x[[/a]]",
+        "[[a href=\"java\tscript:alert(1)\"]]x[[/a]]",
+        "[[a href=\"data:text/html,<script>alert(1)</script>\"]]x[[/a]]",
+        "[[image level.png link=\"javascript:alert(1)\"]]",
+        "[[image level.png link=\"vbscript:msgbox(1)\"]]",
+    ]{
+        let html = render(source);
+        assert!(html.contains("href=\"#\""), "{source} rendered {html}");
+        assert!(!html.to_lowercase().contains("script:"), "{source} rendered {html}");
+    }
+}
+
+#[test]
+fn ordinary_links_are_kept(){
+    assert!(render("[[a href=\"https://example.com/a?b=c\"]]x[[/a]]").contains("href=\"https://example.com/a?b=c\""));
+    assert!(render("[[a href=\"/level-1#toc0\"]]x[[/a]]").contains("href=\"/level-1#toc0\""));
+    assert!(render("[[image level.png link=\"mailto:someone@example.com\"]]").contains("href=\"mailto:someone@example.com\""));
+    assert!(render("[[image level.png link=\"/level-1\"]]").contains("href=\"/level-1\""));
+}
+
+#[test]
+fn page_links_and_files_use_the_configured_bases(){
+    let includes = HashMap::new();
+    let options = HtmlOptions{fullname: "level-0", page_base: "/pages/name/", page_suffix: "/html", file_base: "https://backrooms-wiki-cn.wikidot.com/local--files/", includes: &includes};
+    let html = render_html("[[[Level 1]]] [[[level-2#top|two]]] [[image map.png]]", &options);
+    assert!(html.contains("href=\"/pages/name/level-1/html\""), "{html}");
+    assert!(html.contains("href=\"/pages/name/level-2/html#top\""), "{html}");
+    assert!(html.contains("src=\"https://backrooms-wiki-cn.wikidot.com/local--files/level-0/map.png\""), "{html}");
+}
@@ -0,0 +1,42 @@
+use wikidot::page_links::{to_fullname, PageInclude, PageLinks};
+
+#[test]
+fn page_links_become_fullnames(){
+    let links = PageLinks::from_source("[[[Level 0]]] [[[level-1|Level 1]]] [[[level-0#top|again]]] [[[component:theme]]] [/sub-layers sub] [[a href=\"/level-2/offset/2\"]]two[[/a]]");
+    assert_eq!(links.pages, vec!["level-0", "level-1", "component:theme", "sub-layers", "level-2"]);
+    assert!(links.urls.is_empty());
+}
+
+#[test]
+fn urls_are_kept_once(){
+    let links = PageLinks::from_source("[https://example.com site] https://example.com [[a href=\"https://wikidot.com\"]]w[[/a]] [[[*https://example.org|new tab]]]");
+    assert_eq!(links.urls, vec!["https://example.com", "https://wikidot.com", "https://example.org"]);
+    assert!(links.pages.is_empty());
+}
+
+#[test]
+fn includes_keep_their_variables(){
+    let links = PageLinks::from_source("[[include Component:Level-Class class=3 | danger = high]]\n[[include :scp-wiki:component:toc]]");
+    assert_eq!(links.includes, vec![
+        PageInclude{
+            target: String::from("component:level-class"),
+            variables: vec![(String::from("class"), String::from("3")), (String::from("danger"), String::from("high"))],
+        },
+        PageInclude{target: String::from(":scp-wiki:component:toc"), variables: Vec::new()},
+    ]);
+}
+
+#[test]
+fn images_are_listed_once(){
+    let links = PageLinks::from_source("[[image a.png]]\n[[=image a.png width=\"50%\"]]\n[[f<image https://example.com/b.jpg]]");
+    assert_eq!(links.images, vec!["a.png", "https://example.com/b.jpg"]);
+}
+
+#[test]
+fn fullnames(){
+    assert_eq!(to_fullname("Level 0#top").as_deref(), Some("level-0"));
+    assert_eq!(to_fullname("/level-0/offset/2").as_deref(), Some("level-0"));
+    assert_eq!(to_fullname("_default:Level 0").as_deref(), Some("level-0"));
+    assert_eq!(to_fullname("Component: Theme ").as_deref(), Some("component:theme"));
+    assert_eq!(to_fullname("#top"), None);
+}
@@ -0,0 +1,41 @@
+use wikidot::page_text::PageText;
+
+#[test]
+fn counts_words_and_reading_time(){
+    let text = PageText::from_source(&"word ".repeat(400));
+    assert_eq!(text.words, 400);
+    assert_eq!(text.characters, 1600);
+    assert_eq!(text.cjk_characters, 0);
+    assert_eq!(text.reading_time, 120);
+}
+
+#[test]
+fn counts_each_cjk_character(){
+    let text = PageText::from_source("後室へようこそ");
+    assert_eq!(text.words, 0);
+    assert_eq!(text.characters, 7);
+    assert_eq!(text.cjk_characters, 7);
+    assert_eq!(text.reading_time, 1);
+}
+
+#[test]
+fn mixed_text_adds_both_rates(){
+    let source = format!("{} {}", "level ".repeat(100), "层".repeat(150));
+    let text = PageText::from_source(&source);
+    assert_eq!(text.words, 100);
+    assert_eq!(text.cjk_characters, 150);
+    assert_eq!(text.reading_time, 60);
+}
+
+#[test]
+fn apostrophes_and_hyphens_stay_in_words(){
+    let text = PageText::from_source("don't use level-0's exits, okay?");
+    assert_eq!(text.words, 5);
+}
+
+#[test]
+fn markup_is_not_counted(){
+    let text = PageText::from_source("**bold** [[[level-1|Level 1]]] [[span style=\"color: red\"]]red[[/span]]");
+    assert_eq!(text.text, "bold Level 1 red");
+    assert_eq!(text.words, 4);
+}
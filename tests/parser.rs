@@ -0,0 +1,43 @@
+use scraper::{Html, Selector};
+use wikidot::{parser::{printuser, to_unix_name}, user::{User, UserType}};
+
+fn parse(fragment: &str) -> User{
+    let html = Html::parse_fragment(fragment);
+    let selector = Selector::parse("span.printuser").unwrap();
+    printuser(html.select(&selector).next().unwrap()).unwrap()
+}
+
+#[test]
+fn normal_printuser(){
+    let user = parse(r#"<span class="printuser avatarhover"><a href="http://www.wikidot.com/user:info/level-walker" onclick="WIKIDOT.page.listeners.userInfo(1234567); return false;"><img class="small" src="https://www.wikidot.com/avatar.php?userid=1234567" alt="Level Walker"></a><a href="http://www.wikidot.com/user:info/level-walker" onclick="WIKIDOT.page.listeners.userInfo(1234567); return false;">Level Walker</a></span>"#);
+    assert_eq!(user.user_type, UserType::Normal);
+    assert_eq!(user.id, Some(1234567));
+    assert_eq!(user.name, "Level Walker");
+    assert_eq!(user.unix_name.as_deref(), Some("level-walker"));
+}
+
+#[test]
+fn deleted_printuser(){
+    let user = parse(r#"<span class="printuser deleted" data-id="7654321"><img class="small" src="https://www.wikidot.com/common--images/avatars/default/a16.png" alt="">(account deleted)</span>"#);
+    assert_eq!(user.user_type, UserType::Deleted);
+    assert_eq!(user.id, Some(7654321));
+}
+
+#[test]
+fn anonymous_printuser_has_no_id(){
+    let user = parse(r#"<span class="printuser anonymous"><a href="javascript:;" onclick="WIKIDOT.page.listeners.anonymousUserInfo('203.0.113.x'); return false;"><img class="small" src="https://www.wikidot.com/common--images/avatars/default/a16.png" alt=""></a><a href="javascript:;" onclick="WIKIDOT.page.listeners.anonymousUserInfo('203.0.113.x'); return false;">Anonymous <span class="ip">(203.0.113.x)</span></a></span>"#);
+    assert_eq!(user.user_type, UserType::Anonymous);
+    assert_eq!(user.id, None);
+    assert_eq!(user.ip.as_deref(), Some("203.0.113.x"));
+}
+
+#[test]
+fn unix_names(){
+    assert_eq!(to_unix_name("  Level Walker  "), "level-walker");
+    assert_eq!(to_unix_name("Foo -- Bar!"), "foo-bar");
+    assert_eq!(to_unix_name("component:Theme_2"), "component:theme-2");
+    assert_eq!(to_unix_name("_Template"), "_template");
+    assert_eq!(to_unix_name("fragment:_Start"), "fragment:_start");
+    assert_eq!(to_unix_name("Tale - :Name"), "tale:name");
+    assert_eq!(to_unix_name("---"), "");
+}
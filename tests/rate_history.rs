@@ -0,0 +1,122 @@
+mod common;
+
+use std::collections::HashMap;
+use common::{at, record_poll};
+use wikidot::mongo_page::{MongoPage, RatingPoint, VoteChange};
+
+fn page() -> MongoPage{
+    common::page(1, 1)
+}
+
+fn votes(pairs: &[(i32, i8)]) -> HashMap<i32, i8>{
+    pairs.iter().copied().collect()
+}
+
+// Records every poll the way update_page does and returns the page
+fn record(polls: &[(i64, HashMap<i32, i8>)]) -> MongoPage{
+    let mut page = page();
+    for (hour, poll) in polls{
+        record_poll(&mut page, *hour, poll.clone());
+    }
+    page
+}
+
+#[test]
+fn replays_every_recorded_poll(){
+    let polls = vec![
+        (0, votes(&[(10, 1), (11, 1)])),
+        (6, votes(&[(10, 1), (11, -1), (12, 1)])),
+        (12, votes(&[(11, -1), (12, 1), (13, -1)])),
+        (18, votes(&[(10, 1), (11, -1), (12, 1), (13, -1)])),
+    ];
+    let page = record(&polls);
+    assert_eq!(page.rate_history.len(), polls.len());
+    for (hour, poll) in &polls{
+        assert_eq!(&page.votes_at(at(*hour)), poll, "votes at hour {hour}");
+        assert_eq!(&page.votes_at(at(*hour + 1)), poll, "votes just after hour {hour}");
+    }
+}
+
+#[test]
+fn only_the_latest_entry_keeps_every_vote(){
+    let page = record(&[
+        (0, votes(&[(10, 1), (11, 1), (12, 1)])),
+        (6, votes(&[(10, 1), (11, -1), (13, 1)])),
+    ]);
+    let stored = |i: usize| page.rate_history[i].votes.iter()
+        .map(|(user_id, vote)| (user_id.parse::<i32>().unwrap(), *vote))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(stored(0), votes(&[(11, 1), (12, 1), (13, 0)]));
+    assert_eq!(stored(1), votes(&[(10, 1), (11, -1), (13, 1)]));
+}
+
+#[test]
+fn unchanged_polls_add_no_entries(){
+    let page = record(&[
+        (0, votes(&[(10, 1)])),
+        (6, votes(&[(10, 1)])),
+        (12, votes(&[(10, 1), (11, 1)])),
+        (18, votes(&[(10, 1), (11, 1)])),
+    ]);
+    assert_eq!(page.rate_history.len(), 2);
+    assert_eq!(page.votes_at(at(6)), votes(&[(10, 1)]));
+    assert_eq!(page.votes_at(at(18)), votes(&[(10, 1), (11, 1)]));
+}
+
+#[test]
+fn removed_and_recast_votes(){
+    let polls = vec![
+        (0, votes(&[(10, 1), (11, -1)])),
+        (6, votes(&[(11, -1)])),
+        (12, votes(&[])),
+        (18, votes(&[(10, -1), (11, -1)])),
+    ];
+    let page = record(&polls);
+    for (hour, poll) in &polls{
+        assert_eq!(&page.votes_at(at(*hour)), poll, "votes at hour {hour}");
+    }
+}
+
+#[test]
+fn nothing_before_the_first_poll(){
+    let recorded = record(&[(6, votes(&[(10, 1)])), (12, votes(&[(10, -1)]))]);
+    assert!(recorded.votes_at(at(5)).is_empty());
+    assert!(page().votes_at(at(5)).is_empty());
+    assert!(page().rating_timeline().is_empty());
+}
+
+#[test]
+fn timeline_follows_the_replayed_votes(){
+    let page = record(&[
+        (0, votes(&[(10, 1), (11, 1)])),
+        (6, votes(&[(10, 1), (11, -1), (12, -1)])),
+        (12, votes(&[(12, 1)])),
+    ]);
+    let point = |hour, up, down, rating| RatingPoint{timestamp: at(hour), up, down, rating};
+    assert_eq!(page.rating_timeline(), vec![
+        point(0, 2, 0, 2),
+        point(6, 1, 2, -1),
+        point(12, 1, 0, 1),
+    ]);
+    for point in page.rating_timeline(){
+        let entry = page.rate_history.iter().find(|entry| entry.timestamp == point.timestamp).unwrap();
+        assert_eq!((entry.up, entry.down), (point.up, point.down));
+    }
+}
+
+#[test]
+fn lists_every_vote_change(){
+    let page = record(&[
+        (0, votes(&[(10, 1), (11, 1)])),
+        (6, votes(&[(10, -1), (11, 1), (12, 1)])),
+        (12, votes(&[(10, -1), (12, 1)])),
+    ]);
+    let change = |hour, user_id, from, to| VoteChange{timestamp: at(hour), user_id, from, to};
+    assert_eq!(page.vote_changes(), vec![
+        change(0, 10, 0, 1),
+        change(0, 11, 0, 1),
+        change(6, 10, 1, -1),
+        change(6, 12, 0, 1),
+        change(12, 11, 1, 0),
+    ]);
+}
@@ -0,0 +1,47 @@
+use scraper::{Html, Selector};
+use wikidot::{site_changes::parse_change, user::UserType};
+
+const CHANGE_ROW: &str = r#"<div class="changes-list-item">
+<table class="page-changes-list">
+<tr>
+<td class="title"><a href="http://scp-wiki-cn.wikidot.com/scp-cn-001">SCP-CN-001</a></td>
+<td class="flags"><span class="spantip" title="page content changed">S</span><span class="spantip" title="tags changed">A</span></td>
+<td class="mod-date"><span class="odate time_1700000000 format_%25e%20%25b%20%25Y%2C%20%25H%3A%25M%7Cagohover">14 Nov 2023 22:13</span></td>
+<td class="revision-no">(rev. 12)</td>
+<td class="mod-by"><span class="printuser avatarhover"><a href="http://www.wikidot.com/user:info/level-walker" onclick="WIKIDOT.page.listeners.userInfo(1234567); return false;"><img class="small" src="https://www.wikidot.com/avatar.php?userid=1234567" alt="Level Walker"></a><a href="http://www.wikidot.com/user:info/level-walker" onclick="WIKIDOT.page.listeners.userInfo(1234567); return false;">Level Walker</a></span></td>
+</tr>
+</table>
+<div class="comments">fix typo</div>
+</div>"#;
+
+fn parse(fragment: &str) -> wikidot::site_changes::SiteChange{
+    let html = Html::parse_fragment(fragment);
+    let selector = Selector::parse("div.changes-list-item").unwrap();
+    parse_change(html.select(&selector).next().unwrap()).unwrap()
+}
+
+#[test]
+fn change_row_is_parsed(){
+    let change = parse(CHANGE_ROW);
+    assert_eq!(change.fullname, "scp-cn-001");
+    assert_eq!(change.title, "SCP-CN-001");
+    assert_eq!(change.revision_no, Some(12));
+    assert_eq!(change.flags, vec!['S', 'A']);
+    assert_eq!(change.changed_at.map(|time| time.timestamp_millis()), Some(1_700_000_000_000));
+    assert_eq!(change.comment, "fix typo");
+}
+
+#[test]
+fn change_author_comes_from_the_printuser(){
+    let user = parse(CHANGE_ROW).changed_by;
+    assert_eq!(user.user_type, UserType::Normal);
+    assert_eq!(user.id, Some(1234567));
+    assert_eq!(user.name, "Level Walker");
+    assert_eq!(user.unix_name.as_deref(), Some("level-walker"));
+}
+
+#[test]
+fn new_page_has_no_revision_number(){
+    let change = parse(&CHANGE_ROW.replace("(rev. 12)", "(new)"));
+    assert_eq!(change.revision_no, None);
+}
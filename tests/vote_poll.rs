@@ -0,0 +1,41 @@
+mod common;
+
+use common::at;
+use wikidot::mongo_poll::{select_due, PollCandidate, PollTier};
+
+fn candidate(page_id: i32, tier: PollTier, polled: i64) -> PollCandidate{
+    PollCandidate{page_id, fullname: format!("page-{page_id}"), tier, polled: at(polled)}
+}
+
+#[test]
+fn tiers_follow_page_activity(){
+    let now = at(1000);
+    assert_eq!(PollTier::of(Some(at(990)), Some(at(990)), Some(at(990)), now), PollTier::Hot);
+    assert_eq!(PollTier::of(Some(at(0)), Some(at(0)), Some(at(995)), now), PollTier::Hot);
+    assert_eq!(PollTier::of(Some(at(900)), Some(at(900)), Some(at(900)), now), PollTier::Warm);
+    assert_eq!(PollTier::of(Some(at(0)), Some(at(900)), Some(at(0)), now), PollTier::Warm);
+    assert_eq!(PollTier::of(Some(at(0)), Some(at(0)), Some(at(960)), now), PollTier::Warm);
+    assert_eq!(PollTier::of(Some(at(0)), Some(at(0)), Some(at(0)), now), PollTier::Cold);
+    assert_eq!(PollTier::of(None, None, None, now), PollTier::Cold);
+}
+
+#[test]
+fn most_overdue_pages_come_first(){
+    let now = at(100);
+    let candidates = vec![
+        candidate(1, PollTier::Cold, 90),
+        candidate(2, PollTier::Hot, 99),
+        candidate(3, PollTier::Warm, 96),
+        candidate(4, PollTier::Cold, 50),
+    ];
+    let due = select_due(candidates, now, 3);
+    assert_eq!(due.iter().map(|candidate| candidate.page_id).collect::<Vec<_>>(), vec![2, 4, 3]);
+}
+
+#[test]
+fn the_whole_budget_is_spent(){
+    let now = at(100);
+    let candidates = (1..=10).map(|page_id| candidate(page_id, PollTier::Cold, 99)).collect::<Vec<_>>();
+    assert_eq!(select_due(candidates.clone(), now, 4).len(), 4);
+    assert_eq!(select_due(candidates, now, 20).len(), 10);
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use std::collections::HashMap;
+use common::{at, record_poll};
+use mongodb::bson::DateTime;
+use wikidot::{mongo_page::MongoPage, vote_report::{analyze, FindingKind}};
+
+fn page(id: i32, author: i32, polls: &[(i64, &[(i32, i8)])]) -> MongoPage{
+    let mut page = common::page(id, author);
+    for (hour, poll) in polls{
+        record_poll(&mut page, *hour, poll.iter().copied());
+    }
+    page
+}
+
+fn kinds(pages: &[MongoPage], joins: &HashMap<i32, DateTime>) -> Vec<(FindingKind, Vec<i32>)>{
+    analyze(pages, joins).into_iter().map(|finding| (finding.kind, finding.users)).collect()
+}
+
+#[test]
+fn ordinary_voting_is_not_flagged(){
+    let pages = (1..=12)
+        .map(|id| page(id, id, &[(0, &[(10, 1), (11, -1)]), (6, &[(10, 1), (11, -1), (12 + id, 1)])]))
+        .collect::<Vec<_>>();
+    assert!(analyze(&pages, &HashMap::new()).is_empty());
+}
+
+#[test]
+fn accounts_voting_together_form_a_cluster(){
+    let pages = (1..=12)
+        .map(|id| page(id, 1, &[(0, &[(10, 1), (11, 1), (12, 1), (20 + id, -1)])]))
+        .collect::<Vec<_>>();
+    let findings = analyze(&pages, &HashMap::new());
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].kind, FindingKind::VotingCluster);
+    assert_eq!(findings[0].users, vec![10, 11, 12]);
+    assert_eq!(findings[0].evidence.len(), 3);
+}
+
+#[test]
+fn downvote_burst_on_one_author(){
+    let mut pages = (1..=3)
+        .map(|id| page(id, 7, &[(0, &[(1, 1)]), (12, &[(1, 1), (10, -1), (11, -1)])]))
+        .collect::<Vec<_>>();
+    pages.push(page(4, 8, &[(0, &[(1, 1)]), (12, &[(1, 1), (10, -1), (11, -1)])]));
+    let findings = analyze(&pages, &HashMap::new());
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].kind, FindingKind::DownvoteBurst);
+    assert_eq!(findings[0].users, vec![10, 11]);
+    assert_eq!(findings[0].pages, vec!["page-1", "page-2", "page-3"]);
+    assert_eq!((findings[0].start, findings[0].end), (Some(at(12)), Some(at(12))));
+}
+
+#[test]
+fn votes_at_the_first_poll_have_no_known_time(){
+    let pages = (1..=3)
+        .map(|id| page(id, 7, &[(12, &[(10, -1), (11, -1)])]))
+        .collect::<Vec<_>>();
+    let joins = HashMap::from([(10, at(0)), (11, at(0))]);
+    assert!(analyze(&pages, &joins).is_empty());
+}
+
+#[test]
+fn new_accounts_voting_right_after_joining(){
+    let pages = (1..=3)
+        .map(|id| page(id, id, &[(0, &[(1, 1)]), (6, &[(1, 1), (10, 1), (11, 1)])]))
+        .collect::<Vec<_>>();
+    let joins = HashMap::from([(10, at(2)), (11, at(-100))]);
+    assert_eq!(kinds(&pages, &joins), vec![(FindingKind::NewAccountVotes, vec![10])]);
+}
+
+#[test]
+fn repeated_reversals_on_one_author(){
+    let pages = vec![
+        page(1, 7, &[(0, &[(10, 1)]), (6, &[(10, -1)])]),
+        page(2, 7, &[(0, &[(10, -1)]), (6, &[]), (12, &[(10, 1)])]),
+        page(3, 8, &[(0, &[(10, 1)]), (6, &[(10, -1)])]),
+    ];
+    let findings = analyze(&pages, &HashMap::new());
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].kind, FindingKind::VoteReversals);
+    assert_eq!(findings[0].users, vec![10]);
+    assert_eq!(findings[0].pages, vec!["page-1", "page-2"]);
+}
+
+#[test]
+fn crowds_on_popular_pages_are_not_clusters(){
+    let crowd = (100..250).map(|user_id| (user_id, 1)).collect::<Vec<_>>();
+    let pages = (1..=12)
+        .map(|id| page(id, id, &[(0, &crowd)]))
+        .collect::<Vec<_>>();
+    assert!(analyze(&pages, &HashMap::new()).is_empty());
+}
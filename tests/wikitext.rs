@@ -0,0 +1,129 @@
+use wikidot::wikitext::{parse, walk, Node, NodeKind, MAX_NESTING};
+
+fn texts(nodes: &[Node]) -> Vec<String>{
+    let mut texts = Vec::new();
+    walk(nodes, &mut |node| if let NodeKind::Text(text) = &node.kind {
+        texts.push(text.clone());
+    });
+    texts
+}
+
+fn elements(nodes: &[Node]) -> Vec<(String, usize)>{
+    let mut elements = Vec::new();
+    walk(nodes, &mut |node| match &node.kind {
+        NodeKind::Element{name, children, ..} => elements.push((name.clone(), children.len())),
+        NodeKind::Format{style, children} => elements.push((format!("{style:?}"), children.len())),
+        _ => (),
+    });
+    elements
+}
+
+fn assert_spans(source: &str){
+    walk(&parse(source), &mut |node| {
+        assert!(node.span.start <= node.span.end && node.span.end <= source.len(), "{:?} in {source:?}", node.span);
+    });
+}
+
+#[test]
+fn empty_input(){
+    assert!(parse("").is_empty());
+    assert!(parse("\n  \n\n").is_empty());
+}
+
+#[test]
+fn unclosed_tag(){
+    let nodes = parse("[[div class=\"a\"]]\ntext");
+    assert_eq!(elements(&nodes), vec![(String::from("div"), 0)]);
+    assert_eq!(texts(&nodes), vec!["text"]);
+}
+
+#[test]
+fn mismatched_closing_tag_stays_text(){
+    let nodes = parse("[[span]]a[[/div]]b");
+    assert_eq!(elements(&nodes), vec![(String::from("span"), 0)]);
+    assert_eq!(texts(&nodes), vec!["a[[/div]]b"]);
+}
+
+#[test]
+fn closing_an_outer_tag_ends_the_inner_one(){
+    let nodes = parse("[[div]]\n[[span]]x[[/div]]\n[[/span]]");
+    assert_eq!(elements(&nodes), vec![(String::from("div"), 1), (String::from("span"), 1)]);
+    assert_eq!(texts(&nodes), vec!["x", "[[/span]]"]);
+}
+
+#[test]
+fn nested_tags_of_one_name(){
+    let nodes = parse("[[size 80%]]a[[size 90%]]b[[/size]]c[[/size]]");
+    assert_eq!(elements(&nodes), vec![(String::from("size"), 3), (String::from("size"), 1)]);
+    assert_eq!(texts(&nodes), vec!["a", "b", "c"]);
+}
+
+#[test]
+fn nested_formatting(){
+    let nodes = parse("**bold //nested// end**");
+    assert_eq!(elements(&nodes), vec![(String::from("Bold"), 3), (String::from("Italic"), 1)]);
+    assert_eq!(texts(&nodes), vec!["bold ", "nested", " end"]);
+}
+
+#[test]
+fn overlapping_formatting(){
+    let nodes = parse("**bold //both** italic//");
+    assert_eq!(elements(&nodes), vec![(String::from("Bold"), 1)]);
+    assert_eq!(texts(&nodes), vec!["bold //both", " italic//"]);
+}
+
+#[test]
+fn unclosed_formatting_stays_text(){
+    let nodes = parse("**unclosed");
+    assert!(elements(&nodes).is_empty());
+    assert_eq!(texts(&nodes), vec!["**unclosed"]);
+}
+
+#[test]
+fn stray_brackets_stay_text(){
+    for source in ["a ]] b [[ c", "[[[page", "x]]y[[/]]", "]]", "[[", "[[/"]{
+        let nodes = parse(source);
+        assert!(elements(&nodes).is_empty(), "{source:?}");
+        assert_eq!(texts(&nodes).concat(), source);
+    }
+}
+
+#[test]
+fn spans_stay_inside_the_source(){
+    for source in ["[[div]]\n**a //b** c//\n[[/span]]", "[[[a|b]]] ]] [[", "* a\n** b\n||~ c||", "> q\n>> r [[/div]]", "[[[page]]] **x"]{
+        assert_spans(source);
+    }
+}
+
+#[test]
+fn many_unclosed_tags(){
+    let source = "[[span]]x".repeat(20000) + &"[[/div]]".repeat(20000);
+    let nodes = parse(&source);
+    assert_eq!(elements(&nodes).len(), 20000);
+    assert_spans(&source);
+}
+
+#[test]
+fn deep_nesting_is_capped(){
+    let source = "[[span]]x".repeat(10000) + &"[[/span]]".repeat(10000);
+    let nodes = parse(&source);
+    fn depth(node: &Node) -> usize{
+        1 + node.children().iter().map(depth).max().unwrap_or(0)
+    }
+    assert!(nodes.iter().map(depth).max().unwrap() <= MAX_NESTING + 3);
+    assert_spans(&source);
+}
+
+#[test]
+fn deep_formatting_is_capped(){
+    let source = "[[span]]**a //b ".repeat(200) + &"c// d** e[[/span]]".repeat(200);
+    assert_spans(&source);
+}
+
+#[test]
+fn long_paragraph_with_unclosed_formatting(){
+    let source = "**a //b __c ".repeat(20000);
+    let nodes = parse(&source);
+    assert!(elements(&nodes).is_empty());
+    assert_eq!(texts(&nodes).concat(), source);
+}